@@ -0,0 +1,159 @@
+use std::{collections::BTreeMap, ffi::OsString};
+
+use clap::Command;
+use configuration::AppConfigManager;
+use utils::core_types::{CoreError, CoreResult};
+
+/// The configured aliases, from the alias name to the arguments it expands to.
+pub fn configured() -> BTreeMap<String, String> {
+    AppConfigManager::get::<BTreeMap<String, String>>("alias").unwrap_or_default()
+}
+
+/// Whether `name` is taken by a built-in command, which always wins over an alias.
+pub fn is_shadowed(command: &Command, name: &str) -> bool {
+    command.find_subcommand(name).is_some()
+}
+
+/// A help section listing `aliases`, or `None` if there are none.
+pub fn help_section(command: &Command, aliases: &BTreeMap<String, String>) -> Option<String> {
+    let width = aliases.keys().map(String::len).max()?;
+
+    let lines: Vec<String> = aliases
+        .iter()
+        .map(|(name, expansion)| {
+            let note = if is_shadowed(command, name) {
+                " (ignored, shadowed by a built-in command)"
+            } else {
+                ""
+            };
+            format!("  {name:<width$}  {expansion}{note}")
+        })
+        .collect();
+
+    Some(format!("Aliases:\n{}", lines.join("\n")))
+}
+
+/// `command` with a subcommand for each alias, so completions offer them.
+pub fn with_alias_subcommands(command: Command, aliases: &BTreeMap<String, String>) -> Command {
+    let subcommands: Vec<Command> = aliases
+        .iter()
+        .filter(|(name, _)| !is_shadowed(&command, name))
+        .map(|(name, expansion)| {
+            Command::new(name.clone()).about(format!("Alias for \"{expansion}\"."))
+        })
+        .collect();
+
+    command.subcommands(subcommands)
+}
+
+/// The command word in `args` (the program name first) and where it is, if clap would
+/// hand it to a plugin. Built-in commands are never aliased.
+fn external_command(command: &Command, args: &[OsString]) -> Option<(usize, String)> {
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .disable_help_flag(true)
+        .disable_version_flag(true)
+        .try_get_matches_from(args)
+        .ok()?;
+
+    let (name, rest) = matches.subcommand()?;
+    if is_shadowed(command, name) {
+        return None;
+    }
+
+    // Everything after the command word belongs to it.
+    let rest = rest
+        .try_get_raw("")
+        .ok()
+        .flatten()
+        .map_or(0, Iterator::count);
+
+    Some((args.len() - rest - 1, name.to_string()))
+}
+
+/// Replace the command word in `args` with its alias, repeating while the result starts
+/// with another alias.
+pub fn expand(
+    command: &Command, mut args: Vec<OsString>, aliases: &BTreeMap<String, String>,
+) -> CoreResult<Vec<OsString>> {
+    let mut expanded: Vec<String> = Vec::new();
+
+    while let Some((index, name)) = external_command(command, &args) {
+        let Some(expansion) = aliases.get(&name) else {
+            break;
+        };
+
+        if expanded.contains(&name) {
+            expanded.push(name);
+            return Err(CoreError::AliasError(format!(
+                "The alias \"{}\" never stops expanding: {}.",
+                expanded[0],
+                expanded.join(" -> ")
+            ))
+            .into());
+        }
+
+        // Split as a shell would, so quoted arguments keep their spaces.
+        let words = shlex::split(expansion).ok_or_else(|| {
+            CoreError::AliasError(format!(
+                "The alias \"{name}\" has an unclosed quote or trailing backslash."
+            ))
+        })?;
+        if words.is_empty() {
+            return Err(CoreError::AliasError(format!("The alias \"{name}\" is empty.")).into());
+        }
+
+        args.splice(index..=index, words.into_iter().map(OsString::from));
+        expanded.push(name);
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod aliases_tests {
+    use std::{collections::BTreeMap, ffi::OsString};
+
+    use super::expand;
+    use crate::get_command;
+
+    fn args(line: &str) -> Vec<OsString> {
+        line.split_whitespace().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn aliases_expand_in_place() {
+        let aliases = BTreeMap::from(
+            [
+                ("dt", "demo-tasks 8"),
+                ("quiet-dt", "--quiet dt"),
+                ("loop", "again"),
+                ("bare", "dt"),
+                ("again", "-v loop"),
+                ("logs", "demo-tasks"),
+                ("named", "--set 'program.name=My App' dt"),
+                ("unclosed", "--set 'program.name=My App"),
+            ]
+            .map(|(name, expansion)| (name.to_string(), expansion.to_string())),
+        );
+        let expand = |line: &str| expand(&get_command(), args(line), &aliases);
+
+        assert_eq!(
+            args("app -c app.toml --quiet demo-tasks 8 -v"),
+            expand("app -c app.toml quiet-dt -v").unwrap()
+        );
+        assert_eq!(args("app demo-tasks 8"), expand("app bare").unwrap());
+        assert_eq!(args("app logs list"), expand("app logs list").unwrap());
+        assert_eq!(args("app deploy dt"), expand("app deploy dt").unwrap());
+        assert_eq!(
+            ["app", "--set", "program.name=My App", "demo-tasks", "8"].map(OsString::from),
+            expand("app named").unwrap().as_slice()
+        );
+        assert!(expand("app unclosed").is_err());
+        assert!(expand("app loop")
+            .unwrap_err()
+            .to_string()
+            .contains("loop -> again -> loop"));
+    }
+}
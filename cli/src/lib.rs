@@ -1,9 +1,18 @@
-use std::{fmt::Display, path::PathBuf};
+use std::{ffi::OsString, fmt::Display, path::PathBuf, time::Duration};
 
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::{ArgValueCandidates, CompleteEnv, CompletionCandidate};
 use configuration::AppConfigManager;
+use tracing::{warn, Level};
+use tracing_subscriber::filter::Targets;
 use utils::{core_types::CoreResult, project_name_str};
 
+pub mod aliases;
+pub mod plugins;
+
+/// Set to the shell's name when a dynamic completion script calls back into the binary.
+pub const DYNAMIC_COMPLETION_VAR: &str = "COMPLETE";
+
 #[derive(Parser, Debug)]
 #[command(
     name = project_name_str!(),
@@ -17,10 +26,86 @@ pub struct Cli {
     #[arg(short = 'c', long = "config", value_name = "FILE")]
     pub config_path: Option<PathBuf>,
 
+    /// How command results are written to stdout. Logs are never written there by default.
+    #[arg(
+        long = "output",
+        value_name = "FORMAT",
+        default_value = "text",
+        global = true
+    )]
+    pub output: OutputFormat,
+
+    /// Override a config key, such as logging.cli_log_level=TRACE. Numbers and booleans
+    /// are inferred, and arrays and tables are written as JSON. A JSON string, such as
+    /// program.version='"007"', is always a string. Can be repeated.
+    #[arg(
+        long = "set",
+        value_name = "KEY=VALUE",
+        value_parser = parse_key_value,
+        global = true
+    )]
+    pub overrides: Vec<(String, String)>,
+
+    /// How many items batch commands work on at once. Overrides pool.concurrency.
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        global = true
+    )]
+    pub jobs: Option<u64>,
+
+    #[command(flatten)]
+    pub logging: LoggingArgs,
+
+    /// Record span timings to a flamegraph (folded stacks) or Chrome trace (.json) file.
+    #[arg(long = "profile-trace", value_name = "FILE", global = true)]
+    pub profile_trace: Option<PathBuf>,
+
+    /// The profile format. Inferred from the file extension when omitted.
+    #[arg(
+        long = "profile-format",
+        value_name = "FORMAT",
+        global = true,
+        requires = "profile_trace"
+    )]
+    pub profile_format: Option<ProfileFormatArg>,
+
     #[clap(subcommand)]
     pub command: AppCommand,
 }
 
+/// The options deciding what is logged to the console.
+#[derive(Args, Debug, Clone, Default)]
+pub struct LoggingArgs {
+    /// Disable console logging, leaving stdout to command output alone.
+    #[arg(long = "quiet", alias = "no-console-log", global = true)]
+    pub quiet: bool,
+
+    /// Log more to the console. Repeat for more detail, as in -vv.
+    #[arg(short = 'v', long = "verbose", action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Log less to the console. Repeat to log less again, as in -qq.
+    #[arg(short = 'q', long = "quieter", action = ArgAction::Count, global = true)]
+    pub quieter: u8,
+
+    /// The console log level, or per-target directives such as "info,rt=trace".
+    /// Overrides the configured level, and is then raised or lowered by -v and -q.
+    #[arg(
+        long = "log-level",
+        value_name = "LEVEL|DIRECTIVES",
+        value_parser = parse_directives,
+        global = true
+    )]
+    pub log_level: Option<Targets>,
+
+    /// The console log style. Overrides the configured style.
+    #[arg(long = "log-format", value_name = "STYLE", global = true)]
+    pub log_format: Option<LogStyleArg>,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum AppCommand {
     #[clap(name = "file-error", about = "Generate a file not found error.", long_about = None)]
@@ -29,12 +114,126 @@ pub enum AppCommand {
     TasksDemo {
         #[arg(value_name = "NUM_TASKS", default_value = "64")]
         num_tasks: usize,
+        /// How many tasks run at once. Overrides --jobs and pool.concurrency.
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        concurrency: Option<u64>,
     },
+    #[clap(name = "demo-worker", about = "Run flaky background workers under the supervisor.", long_about = None)]
+    WorkerDemo {
+        /// How many workers to run.
+        #[arg(long, value_name = "N", default_value = "2")]
+        workers: usize,
+        /// The chance of each unit of work failing, from 0 to 1.
+        #[arg(long, value_name = "RATE", default_value = "0.1", value_parser = parse_rate)]
+        failure_rate: f64,
+        /// When failed or finished workers restart. Overrides the configured policy.
+        #[arg(long, value_name = "POLICY")]
+        policy: Option<RestartPolicyArg>,
+        /// Return once the workers are started instead of waiting for them to stop, to
+        /// watch them with `status` from the shell.
+        #[arg(long)]
+        detach: bool,
+    },
+    #[clap(name = "status", about = "Show the state of supervised tasks.", long_about = None)]
+    Status,
     #[clap(name = "completion", about = "Generate shell completion scripts.", long_about = None)]
     Completion {
+        /// Generate a script that asks the binary for candidates as you type, so values
+        /// such as config keys complete from the live configuration.
+        #[arg(long, global = true)]
+        dynamic: bool,
         #[clap(subcommand)]
         subcommand: CompletionSubCommand,
     },
+    #[clap(name = "logs", about = "Inspect the rolling log files.", long_about = None)]
+    Logs {
+        #[clap(subcommand)]
+        subcommand: LogsSubCommand,
+    },
+    #[clap(name = "config", about = "Inspect the configuration.", long_about = None)]
+    Config {
+        #[clap(subcommand)]
+        subcommand: ConfigSubCommand,
+    },
+    #[clap(name = "docs", about = "Generate documentation for the command line.", long_about = None)]
+    Docs {
+        #[clap(subcommand)]
+        subcommand: DocsSubCommand,
+    },
+    #[clap(name = "run-script", about = "Run the commands in a file, one per line.", long_about = None)]
+    RunScript {
+        /// The script, or - to read it from stdin. Blank lines and lines starting with # are
+        /// skipped, and ${key} is replaced with the value of a config key.
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+        /// Run every line, even after one fails.
+        #[arg(long, overrides_with = "fail_fast")]
+        continue_on_error: bool,
+        /// Stop at the first line that fails. This is the default.
+        #[arg(long, overrides_with = "continue_on_error")]
+        fail_fast: bool,
+    },
+    #[cfg(feature = "tui")]
+    #[clap(name = "tui", about = "Watch running tasks, live logs and the configuration in a terminal dashboard.", long_about = None)]
+    Tui {
+        /// A command to run while watching, such as "demo-tasks 1000". Its result is shown
+        /// in the status bar.
+        #[arg(
+            value_name = "COMMAND",
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        command: Vec<String>,
+    },
+    #[clap(name = "shell", about = "Run commands interactively, keeping the runtime loaded between them.", long_about = None)]
+    Shell,
+    /// Any other command runs the plugin `rust-starter-template-<command>`, with the
+    /// remaining arguments.
+    #[command(external_subcommand)]
+    Plugin(Vec<String>),
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum ProfileFormatArg {
+    /// Folded stacks for inferno or flamegraph.pl.
+    Folded,
+    /// Chrome trace_event JSON for Perfetto or chrome://tracing.
+    Chrome,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    /// Plain text for reading.
+    #[default]
+    Text,
+    /// Pretty-printed JSON.
+    Json,
+    /// YAML, one document.
+    Yaml,
+    /// Columns aligned under a header row.
+    Table,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum LogStyleArg {
+    /// One line per event, with the fields of every span in scope.
+    Full,
+    /// One line per event, with only the event's own fields.
+    Compact,
+    /// Several lines per event, for reading by eye.
+    Pretty,
+    /// One JSON object per event.
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum RestartPolicyArg {
+    /// Leave the task stopped.
+    Never,
+    /// Restart the task only if it failed.
+    OnFailure,
+    /// Restart the task whenever it stops.
+    Always,
 }
 
 #[derive(Subcommand, PartialEq, Debug)]
@@ -45,36 +244,464 @@ pub enum CompletionSubCommand {
     Zsh,
     #[clap(about = "Generate the autocompletion script for Fish.")]
     Fish,
+    #[clap(about = "Generate the autocompletion script for PowerShell.")]
+    Powershell,
+    #[clap(about = "Generate the autocompletion script for Elvish.")]
+    Elvish,
+    #[clap(about = "Generate the autocompletion script for Nushell.")]
+    Nushell,
+    #[clap(about = "Write the autocompletion script to where the shell looks for it.")]
+    Install {
+        /// One of auto, bash, zsh, fish, powershell, elvish or nushell. Auto detects the
+        /// shell from $SHELL.
+        #[arg(long, value_name = "SHELL", default_value = "auto", value_parser = parse_shell_selection)]
+        shell: ShellSelection,
+    },
+    #[clap(about = "Remove an autocompletion script written by install.")]
+    Uninstall {
+        /// One of auto, bash, zsh, fish, powershell, elvish or nushell. Auto detects the
+        /// shell from $SHELL.
+        #[arg(long, value_name = "SHELL", default_value = "auto", value_parser = parse_shell_selection)]
+        shell: ShellSelection,
+    },
+}
+
+impl CompletionSubCommand {
+    /// The shell to generate a script for, unless installing or uninstalling.
+    pub fn shell(&self) -> Option<CompletionShell> {
+        match self {
+            Self::Bash => Some(CompletionShell::Bash),
+            Self::Zsh => Some(CompletionShell::Zsh),
+            Self::Fish => Some(CompletionShell::Fish),
+            Self::Powershell => Some(CompletionShell::PowerShell),
+            Self::Elvish => Some(CompletionShell::Elvish),
+            Self::Nushell => Some(CompletionShell::Nushell),
+            Self::Install { .. } | Self::Uninstall { .. } => None,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    #[value(name = "powershell")]
+    PowerShell,
+    Elvish,
+    Nushell,
+}
+
+impl Display for CompletionShell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bash => write!(f, "Bash"),
+            Self::Zsh => write!(f, "Zsh"),
+            Self::Fish => write!(f, "Fish"),
+            Self::PowerShell => write!(f, "PowerShell"),
+            Self::Elvish => write!(f, "Elvish"),
+            Self::Nushell => write!(f, "Nushell"),
+        }
+    }
+}
+
+/// A shell named on the command line, or `auto` to detect it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShellSelection {
+    Auto,
+    Shell(CompletionShell),
+}
+
+fn parse_shell_selection(value: &str) -> Result<ShellSelection, String> {
+    if value.eq_ignore_ascii_case("auto") {
+        return Ok(ShellSelection::Auto);
+    }
+
+    CompletionShell::from_str(value, true).map(ShellSelection::Shell)
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+pub enum LogsSubCommand {
+    #[clap(about = "List the log files, oldest first.")]
+    List,
+    #[clap(about = "Print the last lines of the log files.")]
+    Tail {
+        /// The number of lines to print.
+        #[arg(short = 'n', long, default_value = "10")]
+        lines: usize,
+        /// Keep printing lines as they are written, following rotation.
+        #[arg(short = 'f', long)]
+        follow: bool,
+    },
+    #[clap(about = "Search the log files for lines matching a regular expression.")]
+    Grep {
+        #[arg(value_name = "PATTERN")]
+        pattern: String,
+        /// Only show lines at this level or more severe.
+        #[arg(long, value_name = "LEVEL")]
+        level: Option<Level>,
+        /// Only show lines written within this long, such as 30s, 15m, 1h or 2d.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        since: Option<Duration>,
+    },
+    #[clap(about = "Print a log file, by name within the log directory or by path.")]
+    Show {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+pub enum ConfigSubCommand {
+    #[clap(about = "List the configuration sources, from lowest to highest precedence.")]
+    Sources,
+    #[clap(about = "Print the value of a config key, such as logging.cli_log_level.")]
+    Get {
+        #[arg(value_name = "KEY", add = ArgValueCandidates::new(config_key_candidates))]
+        key: String,
+    },
+    #[clap(about = "Print a configured database by name.")]
+    Database {
+        #[arg(value_name = "NAME", add = ArgValueCandidates::new(database_candidates))]
+        name: String,
+        /// Print the URL as configured, rather than masked by the redaction rules.
+        #[arg(long = "show-secrets")]
+        show_secrets: bool,
+    },
+}
+
+/// Config key paths for dynamic completion.
+fn config_key_candidates() -> Vec<CompletionCandidate> {
+    AppConfigManager::keys()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Configured database names for dynamic completion.
+fn database_candidates() -> Vec<CompletionCandidate> {
+    AppConfigManager::clone_to_app_config()
+        .map(|config| config.databases)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|database| CompletionCandidate::new(database.name))
+        .collect()
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+pub enum DocsSubCommand {
+    #[clap(about = "Write roff man pages for every command.")]
+    Man {
+        /// The directory to write the pages to. Created if missing.
+        #[arg(long = "out-dir", value_name = "DIR", default_value = "man")]
+        out_dir: PathBuf,
+    },
+    #[clap(about = "Print a Markdown reference for every command.")]
+    Markdown,
+}
+
+/// Parse a `KEY=VALUE` pair. The value may itself contain `=`.
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("\"{value}\" is not of the form KEY=VALUE.")),
+    }
+}
+
+/// Parse a level such as `debug`, or directives such as `info,rt=trace`.
+fn parse_directives(value: &str) -> Result<Targets, String> {
+    value.parse::<Targets>().map_err(|e| e.to_string())
+}
+
+/// Parse a probability from 0 to 1.
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("\"{value}\" isn't a number from 0 to 1.")),
+    }
+}
+
+/// Parse a duration made of a number and a unit of `s`, `m`, `h` or `d`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("\"{value}\" has no unit. Use s, m, h or d."))?;
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("\"{value}\" does not start with a number."))?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Unknown unit \"{unit}\". Use s, m, h or d.")),
+    };
+
+    Ok(Duration::from_secs(amount * seconds))
 }
 
 impl Display for AppCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::FileError => write!(f, "FileError"),
-            Self::TasksDemo { num_tasks } => write!(f, "TasksDemo({num_tasks})"),
-            Self::Completion { subcommand } => write!(
+            Self::Shell => write!(f, "Shell"),
+            #[cfg(feature = "tui")]
+            Self::Tui { .. } => write!(f, "Tui"),
+            Self::RunScript { file, .. } => write!(f, "RunScript({})", file.display()),
+            Self::TasksDemo { num_tasks, .. } => write!(f, "TasksDemo({num_tasks})"),
+            Self::WorkerDemo { workers, .. } => write!(f, "WorkerDemo({workers})"),
+            Self::Status => write!(f, "Status"),
+            Self::Completion { subcommand, .. } => match subcommand {
+                CompletionSubCommand::Install { .. } => write!(f, "InstallCompletions"),
+                CompletionSubCommand::Uninstall { .. } => write!(f, "UninstallCompletions"),
+                _ => match subcommand.shell() {
+                    Some(shell) => write!(f, "GenerateCompletions({shell})"),
+                    None => write!(f, "GenerateCompletions"),
+                },
+            },
+            Self::Logs { subcommand } => write!(
+                f,
+                "Logs({})",
+                match subcommand {
+                    LogsSubCommand::List => "List",
+                    LogsSubCommand::Tail { .. } => "Tail",
+                    LogsSubCommand::Grep { .. } => "Grep",
+                    LogsSubCommand::Show { .. } => "Show",
+                }
+            ),
+            Self::Config { subcommand } => write!(
                 f,
-                "GenerateCompletions({})",
+                "Config({})",
                 match subcommand {
-                    CompletionSubCommand::Bash => "Bash",
-                    CompletionSubCommand::Zsh => "Zsh",
-                    CompletionSubCommand::Fish => "Fish",
+                    ConfigSubCommand::Sources => "Sources",
+                    ConfigSubCommand::Get { .. } => "Get",
+                    ConfigSubCommand::Database { .. } => "Database",
                 }
             ),
+            Self::Plugin(args) => write!(
+                f,
+                "Plugin({})",
+                args.first().map(String::as_str).unwrap_or_default()
+            ),
+            Self::Docs { subcommand } => write!(
+                f,
+                "Docs({})",
+                match subcommand {
+                    DocsSubCommand::Man { .. } => "Man",
+                    DocsSubCommand::Markdown => "Markdown",
+                }
+            ),
+        }
+    }
+}
+
+impl AppCommand {
+    /// The subcommand name, without arguments, for use where cardinality matters.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::FileError => "file-error",
+            Self::TasksDemo { .. } => "demo-tasks",
+            Self::WorkerDemo { .. } => "demo-worker",
+            Self::Status => "status",
+            Self::Completion { .. } => "completion",
+            Self::Logs { .. } => "logs",
+            Self::Config { .. } => "config",
+            Self::Docs { .. } => "docs",
+            Self::Shell => "shell",
+            #[cfg(feature = "tui")]
+            Self::Tui { .. } => "tui",
+            Self::RunScript { .. } => "run-script",
+            Self::Plugin(_) => "plugin",
+        }
+    }
+}
+
+impl LoggingArgs {
+    /// The console log level, given the configured one. `--log-level` replaces it, then each
+    /// `-v` raises it and each `-q` lowers it. `None` means the console should be silent.
+    pub fn console_level(&self, configured: Level) -> Option<Level> {
+        const LEVELS: [Level; 5] = [
+            Level::ERROR,
+            Level::WARN,
+            Level::INFO,
+            Level::DEBUG,
+            Level::TRACE,
+        ];
+
+        let base = match self.log_level.as_ref().and_then(Targets::default_level) {
+            Some(level) => level.into_level()?,
+            None => configured,
+        };
+
+        let index = LEVELS.iter().position(|level| *level == base).unwrap_or(2) as i32
+            + i32::from(self.verbose)
+            - i32::from(self.quieter);
+
+        usize::try_from(index)
+            .ok()
+            .map(|index| LEVELS[index.min(LEVELS.len() - 1)])
+    }
+}
+
+/// Answer a completion request from a script generated with `completion --dynamic` and
+/// exit, if the process was started for one. Must run before anything is written to stdout.
+pub fn complete_from_env() {
+    let completing = std::env::var_os(DYNAMIC_COMPLETION_VAR)
+        .is_some_and(|shell| !shell.is_empty() && shell != "0");
+
+    // Candidates come from the config the command being completed would run with. The
+    // words being completed follow a "--".
+    if completing {
+        let words: Vec<OsString> = std::env::args_os()
+            .skip_while(|arg| arg != "--")
+            .skip(1)
+            .collect();
+
+        if let Some(config_path) = config_path(&words).filter(|path| path.is_file()) {
+            AppConfigManager::add_file_source(config_path);
         }
     }
+
+    CompleteEnv::with_factory(completion_command)
+        .var(DYNAMIC_COMPLETION_VAR)
+        .complete();
+}
+
+/// The configured plugin directory, if any.
+pub fn plugin_dir() -> Option<PathBuf> {
+    AppConfigManager::get::<String>("plugins.dir")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
 }
 
-pub fn cli_match() -> CoreResult<AppCommand> {
-    let cli = Cli::parse();
+pub fn cli_match() -> CoreResult<Cli> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+
+    // Aliases can be defined in the config file, so it's loaded before they're expanded.
+    let config_path = config_path(&args);
+    if let Some(config_path) = &config_path {
+        AppConfigManager::add_file_source(config_path.clone());
+    }
+
+    let aliases = aliases::configured();
+    let args = aliases::expand(&get_command(), args, &aliases)?;
+
+    // Only the help shown to users lists plugins and aliases, not generated docs.
+    let mut command = get_command();
+    let sections: Vec<String> = [
+        plugins::help_section(&command, &plugins::discover(plugin_dir().as_deref())),
+        aliases::help_section(&command, &aliases),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !sections.is_empty() {
+        command = command.after_help(sections.join("\n\n"));
+    }
+
+    let cli = Cli::from_arg_matches(&command.get_matches_from(args)).unwrap_or_else(|e| e.exit());
+
+    let name = cli.command.name();
+    if !matches!(cli.command, AppCommand::Plugin(_)) && aliases.contains_key(name) {
+        warn!("The alias \"{name}\" is ignored, as a built-in command has the same name.");
+    }
+
+    // An alias can name a config file too.
+    if cli.config_path != config_path {
+        if let Some(config_path) = &cli.config_path {
+            AppConfigManager::add_file_source(config_path.clone());
+        }
+    }
 
-    if let Some(config_path) = cli.config_path {
-        AppConfigManager::add_file_source(config_path);
+    for (key, value) in &cli.overrides {
+        AppConfigManager::set(key, value)?;
+    }
+    if let Some(jobs) = cli.jobs {
+        AppConfigManager::set("pool.concurrency", &jobs.to_string())?;
     }
 
-    Ok(cli.command)
+    Ok(cli)
 }
 
 pub fn get_command() -> clap::Command {
     Cli::command()
 }
+
+/// The built-in command most like `name`, if any is close enough to be a likely typo.
+/// Scored the way clap scores suggestions for unknown arguments.
+pub fn similar_command(name: &str) -> Option<String> {
+    get_command()
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .map(|subcommand| subcommand.get_name().to_string())
+        .map(|candidate| (strsim::jaro(name, &candidate), candidate))
+        .filter(|(score, _)| *score > 0.7)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, candidate)| candidate)
+}
+
+/// The command completions are generated from, with the configured aliases.
+pub fn completion_command() -> clap::Command {
+    aliases::with_alias_subcommands(get_command(), &aliases::configured())
+}
+
+/// The config file named in `args`, read without checking anything else.
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    get_command()
+        .ignore_errors(true)
+        .disable_help_flag(true)
+        .disable_version_flag(true)
+        .try_get_matches_from(args)
+        .ok()?
+        .get_one::<PathBuf>("config_path")
+        .cloned()
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use clap::Parser;
+    use tracing::Level;
+
+    use super::{similar_command, Cli};
+
+    #[test]
+    fn console_level_follows_flags() {
+        let level = |args: &[&str]| {
+            Cli::parse_from([&["app"], args, &["file-error"]].concat())
+                .logging
+                .console_level(Level::INFO)
+        };
+
+        assert_eq!(Some(Level::INFO), level(&[]));
+        assert_eq!(Some(Level::TRACE), level(&["-vvvv"]));
+        assert_eq!(Some(Level::WARN), level(&["-vqq"]));
+        assert_eq!(None, level(&["-qqq"]));
+        assert_eq!(
+            Some(Level::DEBUG),
+            level(&["--log-level", "warn,rt=trace", "-vv"])
+        );
+        assert_eq!(None, level(&["--log-level", "off", "-v"]));
+        assert_eq!(Some(Level::WARN), level(&["--quieter"]));
+
+        // --quiet turns console logging off rather than lowering the level.
+        let cli = Cli::parse_from(["app", "--quiet", "file-error"]);
+        assert!(cli.logging.quiet);
+        assert_eq!(0, cli.logging.quieter);
+    }
+
+    #[test]
+    fn typos_suggest_a_similar_command() {
+        assert_eq!(Some("demo-tasks".to_string()), similar_command("demo-task"));
+        assert_eq!(
+            Some("completion".to_string()),
+            similar_command("compleshun")
+        );
+        assert_eq!(None, similar_command("deploy"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use clap::Command;
+use utils::project_name_str;
+
+use crate::aliases::is_shadowed;
+
+/// An executable named `rust-starter-template-<name>`, run for the unknown command `<name>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plugin {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// A help section listing `plugins`, or `None` if there are none.
+pub fn help_section(command: &Command, plugins: &[Plugin]) -> Option<String> {
+    let width = plugins.iter().map(|plugin| plugin.name.len()).max()?;
+
+    let lines: Vec<String> = plugins
+        .iter()
+        .map(|plugin| {
+            let note = if is_shadowed(command, &plugin.name) {
+                " (ignored, shadowed by a built-in command)"
+            } else {
+                ""
+            };
+            format!("  {:<width$}  {}{note}", plugin.name, plugin.path.display())
+        })
+        .collect();
+
+    Some(format!("Plugins:\n{}", lines.join("\n")))
+}
+
+/// The directories searched for plugins, in order: `plugin_dir` if set, then `PATH`.
+fn search_dirs(plugin_dir: Option<&Path>) -> Vec<PathBuf> {
+    plugin_dir
+        .map(Path::to_path_buf)
+        .into_iter()
+        .chain(
+            std::env::var_os("PATH")
+                .iter()
+                .flat_map(std::env::split_paths),
+        )
+        .collect()
+}
+
+/// Whether `name` can name a plugin. Path separators would let a command resolve to a
+/// file outside the directories searched.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.contains(['/', std::path::MAIN_SEPARATOR])
+}
+
+/// Find the plugin for the command `name`. The first match in search order wins.
+pub fn find(name: &str, plugin_dir: Option<&Path>) -> Option<Plugin> {
+    if !is_valid_name(name) {
+        return None;
+    }
+
+    let file_name = format!(
+        "{}-{name}{}",
+        project_name_str!(),
+        std::env::consts::EXE_SUFFIX
+    );
+
+    search_dirs(plugin_dir)
+        .into_iter()
+        .map(|dir| dir.join(&file_name))
+        .find(|path| is_executable(path))
+        .map(|path| Plugin {
+            name: name.to_string(),
+            path,
+        })
+}
+
+/// Every plugin that can be run, sorted by name. Where several share a name, the one
+/// `find` would run is listed.
+pub fn discover(plugin_dir: Option<&Path>) -> Vec<Plugin> {
+    let prefix = format!("{}-", project_name_str!());
+    let mut plugins = BTreeMap::new();
+
+    for dir in search_dirs(plugin_dir) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let Some(name) = entry
+                .file_name()
+                .to_str()
+                .and_then(|file_name| file_name.strip_prefix(&prefix))
+                .map(|name| {
+                    name.strip_suffix(std::env::consts::EXE_SUFFIX)
+                        .unwrap_or(name)
+                        .to_string()
+                })
+            else {
+                continue;
+            };
+
+            if !name.is_empty() && !plugins.contains_key(&name) && is_executable(&path) {
+                plugins.insert(name.clone(), Plugin { name, path });
+            }
+        }
+    }
+
+    plugins.into_values().collect()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(all(test, unix))]
+mod plugins_tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{discover, find, help_section, is_valid_name};
+
+    #[test]
+    fn only_executables_with_the_prefix_are_plugins() {
+        let dir = std::env::temp_dir().join(format!("plugins-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, mode) in [
+            ("rust-starter-template-deploy", 0o755),
+            ("rust-starter-template-notes", 0o644),
+            ("rust-starter-template-shell", 0o755),
+            ("other-tool", 0o755),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        // Only reachable by a name climbing out of the directory.
+        let escape = dir.join("rust-starter-template-..");
+        std::fs::create_dir_all(&escape).unwrap();
+        std::fs::write(escape.join("deploy"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(
+            escape.join("deploy"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        let plugins = discover(Some(&dir));
+        let deploy = find("deploy", Some(&dir));
+        let escaped = find("../deploy", Some(&dir));
+        let notes = find("notes", Some(&dir));
+        let help = help_section(&crate::get_command(), &plugins).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            Some("deploy"),
+            plugins.first().map(|plugin| plugin.name.as_str())
+        );
+        assert!(plugins.iter().all(|plugin| plugin.name != "notes"));
+        assert_eq!(
+            Some(dir.join("rust-starter-template-deploy")),
+            deploy.map(|plugin| plugin.path)
+        );
+        assert!(notes.is_none());
+        assert!(escaped.is_none());
+        assert!(!is_valid_name("../../bin/sh"));
+        assert!(
+            help.contains("rust-starter-template-shell (ignored, shadowed by a built-in command)")
+        );
+    }
+}
@@ -1,13 +1,15 @@
-use std::path::PathBuf;
+use std::{fmt::Display, path::PathBuf};
 
 use app_config::AppConfig;
-use config::{Config, ConfigBuilder};
+use config::{Config, ConfigBuilder, ConfigError, Map, Value, ValueKind};
 use lazy_static::lazy_static;
 use parking_lot::RwLock;
 use utils::core_types::{CoreError, CoreResult};
 
 pub mod app_config;
 
+const ENV_PREFIX: &str = "RUST_STARTER_TEMPLATE";
+
 lazy_static! {
     /// The Global default config builder. Any default start-time configuration should be set here.
     /// A default configuration is hard-coded at compile-time using `include_str!`, then environment overrides are loaded.
@@ -17,23 +19,65 @@ lazy_static! {
             include_str!("../../configs/default_config.toml"),
             config::FileFormat::Toml
         ))
-        .add_source(config::Environment::with_prefix("RUST_STARTER_TEMPLATE"))
+        .add_source(config::Environment::with_prefix(ENV_PREFIX))
     );
+
+    /// The layers making up the configuration, from lowest to highest precedence.
+    static ref CONFIG_SOURCES: RwLock<Vec<ConfigSource>> = RwLock::new(vec![
+        ConfigSource::Defaults,
+        ConfigSource::Environment(format!("{ENV_PREFIX}_")),
+    ]);
+}
+
+/// A layer of configuration. Overrides always take precedence over everything else, and
+/// files over the environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// The defaults compiled into the binary.
+    Defaults,
+    /// Environment variables with the given prefix.
+    Environment(String),
+    File(PathBuf),
+    /// A single key set at runtime, such as by `--set`.
+    Override {
+        key: String,
+        value: String,
+    },
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Defaults => write!(f, "defaults (built in)"),
+            Self::Environment(prefix) => write!(f, "environment ({prefix}*)"),
+            Self::File(path) => write!(f, "file ({})", path.display()),
+            Self::Override { key, value } => write!(f, "override ({key}={value})"),
+        }
+    }
 }
 
 /// The main configuration manager for the application. All config changes should go through here.
 pub struct AppConfigManager {}
 
 impl AppConfigManager {
+    /// Override `key`, taking precedence over every other source. The type of `value` is
+    /// inferred: booleans and numbers are parsed, JSON arrays and objects are read as such,
+    /// and anything else is a string. A quoted JSON string, such as `"007"`, is always
+    /// read as a string.
     pub fn set(key: &str, value: &str) -> CoreResult<()> {
         {
             let mut builder = CONFIG_BUILDER.write();
             *builder = builder
                 .clone()
-                .set_override(key, value)
+                .set_override(key, infer_value(value)?)
                 .map_err(CoreError::from)?;
         }
 
+        CONFIG_SOURCES.write().push(ConfigSource::Override {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+
         Ok(())
     }
 
@@ -60,7 +104,103 @@ impl AppConfigManager {
         *builder = builder
             .clone()
             .add_source(config::File::with_name(file.to_str().unwrap()));
+
+        let mut sources = CONFIG_SOURCES.write();
+        let overrides = sources
+            .iter()
+            .position(|source| matches!(source, ConfigSource::Override { .. }))
+            .unwrap_or(sources.len());
+        sources.insert(overrides, ConfigSource::File(file));
+    }
+
+    /// Every key path in the merged configuration, sorted, including those of tables and
+    /// arrays. Array elements are written as `database[0]`.
+    pub fn keys() -> CoreResult<Vec<String>> {
+        let config = CONFIG_BUILDER
+            .read()
+            .build_cloned()
+            .map_err(CoreError::from)?;
+
+        let mut keys = Vec::new();
+        collect_keys("", &config.cache, &mut keys);
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    /// The sources loaded so far, from lowest to highest precedence.
+    pub fn sources() -> Vec<ConfigSource> {
+        CONFIG_SOURCES.read().clone()
+    }
+}
+
+fn collect_keys(path: &str, value: &Value, keys: &mut Vec<String>) {
+    if !path.is_empty() {
+        keys.push(path.to_string());
+    }
+
+    match &value.kind {
+        ValueKind::Table(table) => {
+            for (key, value) in table {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                collect_keys(&path, value, keys);
+            }
+        }
+        ValueKind::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                collect_keys(&format!("{path}[{i}]"), value, keys);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn infer_value(raw: &str) -> CoreResult<Value> {
+    if let Ok(value) = raw.parse::<bool>() {
+        return Ok(value.into());
+    }
+    if let Ok(value) = raw.parse::<i64>() {
+        return Ok(value.into());
     }
+    // Also rules out words such as "inf" and "NaN".
+    if let Some(value) = raw.parse::<f64>().ok().filter(|value| value.is_finite()) {
+        return Ok(value.into());
+    }
+    if raw.starts_with('[') || raw.starts_with('{') || raw.starts_with('"') {
+        let json = serde_json::from_str::<serde_json::Value>(raw).map_err(|e| {
+            CoreError::from(ConfigError::Message(format!("Invalid JSON \"{raw}\": {e}")))
+        })?;
+        return Ok(json_to_value(json));
+    }
+
+    Ok(raw.into())
+}
+
+fn json_to_value(json: serde_json::Value) -> Value {
+    let kind = match json {
+        serde_json::Value::Null => ValueKind::Nil,
+        serde_json::Value::Bool(value) => ValueKind::Boolean(value),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(value) => ValueKind::I64(value),
+            None => ValueKind::Float(number.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(value) => ValueKind::String(value),
+        serde_json::Value::Array(values) => {
+            ValueKind::Array(values.into_iter().map(json_to_value).collect())
+        }
+        serde_json::Value::Object(object) => ValueKind::Table(
+            object
+                .into_iter()
+                .map(|(key, value)| (key, json_to_value(value)))
+                .collect::<Map<String, Value>>(),
+        ),
+    };
+
+    Value::new(None, kind)
 }
 
 #[cfg(test)]
@@ -120,4 +260,46 @@ mod app_config_manager_tests {
 
         assert_eq!("Foo".to_string(), found_name);
     }
+
+    #[test]
+    fn keys_include_nested_paths() {
+        use crate::AppConfigManager;
+
+        let keys = AppConfigManager::keys().unwrap();
+
+        assert!(keys.contains(&"logging".to_string()));
+        assert!(keys.contains(&"logging.cli_format.color".to_string()));
+        assert!(keys.contains(&"database[0].name".to_string()));
+    }
+
+    #[test]
+    fn set_infers_types() {
+        use crate::{AppConfigManager, ConfigSource};
+
+        AppConfigManager::set("test-infer.flag", "true").unwrap();
+        AppConfigManager::set("test-infer.count", "42").unwrap();
+        AppConfigManager::set("test-infer.list", r#"["a", "b"]"#).unwrap();
+        AppConfigManager::set("test-infer.quoted", r#""007""#).unwrap();
+
+        assert!(AppConfigManager::get::<bool>("test-infer.flag").unwrap());
+        assert_eq!(
+            42,
+            AppConfigManager::get::<u16>("test-infer.count").unwrap()
+        );
+        assert_eq!(
+            vec!["a".to_string(), "b".to_string()],
+            AppConfigManager::get::<Vec<String>>("test-infer.list").unwrap()
+        );
+        assert_eq!(
+            "007",
+            AppConfigManager::get::<String>("test-infer.quoted").unwrap()
+        );
+        assert!(AppConfigManager::set("test-infer.bad", "[1,").is_err());
+        assert!(
+            AppConfigManager::sources().contains(&ConfigSource::Override {
+                key: "test-infer.count".to_string(),
+                value: "42".to_string()
+            })
+        );
+    }
 }
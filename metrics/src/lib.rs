@@ -0,0 +1,47 @@
+use std::{path::Path, sync::Arc};
+
+use lazy_static::lazy_static;
+use utils::core_types::{CoreError, CoreResult};
+
+pub mod registry;
+pub mod server;
+
+pub use registry::{Counter, Gauge, Histogram, MetricsRegistry, DEFAULT_DURATION_BUCKETS};
+
+lazy_static! {
+    static ref METRICS: Arc<MetricsRegistry> = Arc::new(MetricsRegistry::new());
+}
+
+/// The process-wide registry used by the helpers below.
+pub fn global() -> Arc<MetricsRegistry> {
+    METRICS.clone()
+}
+
+/// Get or register a counter in the global registry.
+pub fn counter(name: &str, help: &str, labels: &[(&str, &str)]) -> Counter {
+    METRICS.counter(name, help, labels)
+}
+
+/// Get or register a gauge in the global registry.
+pub fn gauge(name: &str, help: &str, labels: &[(&str, &str)]) -> Gauge {
+    METRICS.gauge(name, help, labels)
+}
+
+/// Get or register a histogram in the global registry, using `buckets` on first registration.
+pub fn histogram(name: &str, help: &str, labels: &[(&str, &str)], buckets: &[f64]) -> Histogram {
+    METRICS.histogram(name, help, labels, buckets)
+}
+
+/// Write the current state of the global registry to `path` in the Prometheus text format.
+pub fn write_snapshot(path: &Path) -> CoreResult<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent).map_err(CoreError::from)?;
+    }
+
+    std::fs::write(path, METRICS.render_prometheus()).map_err(CoreError::from)?;
+
+    Ok(())
+}
@@ -0,0 +1,424 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::RwLock;
+use tracing::warn;
+
+/// Default histogram buckets, in seconds, suited to command and task durations.
+pub const DEFAULT_DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+pub type Labels = Vec<(String, String)>;
+
+/// An `f64` stored in an `AtomicU64`, updated with compare-and-swap.
+#[derive(Debug, Default)]
+struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    fn add(&self, delta: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + delta).to_bits())
+            });
+    }
+}
+
+/// A monotonically increasing count.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn increment(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that can go up and down.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<AtomicF64>);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.set(value);
+    }
+
+    pub fn increment(&self, value: f64) {
+        self.0.add(value);
+    }
+
+    pub fn decrement(&self, value: f64) {
+        self.0.add(-value);
+    }
+
+    pub fn get(&self) -> f64 {
+        self.0.get()
+    }
+}
+
+#[derive(Debug)]
+struct HistogramData {
+    bounds: Vec<f64>,
+    /// Per-bucket counts, not cumulative. The last slot counts values above every bound.
+    buckets: Vec<AtomicU64>,
+    sum: AtomicF64,
+    count: AtomicU64,
+}
+
+/// A distribution of observed values across fixed buckets.
+#[derive(Debug, Clone)]
+pub struct Histogram(Arc<HistogramData>);
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+
+        Self(Arc::new(HistogramData {
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            bounds,
+            sum: AtomicF64::default(),
+            count: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn observe(&self, value: f64) {
+        let index = self
+            .0
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.0.bounds.len());
+
+        self.0.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.0.sum.add(value);
+        self.0.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.0.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.0.sum.get()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Counter(_) => "counter",
+            Self::Gauge(_) => "gauge",
+            Self::Histogram(_) => "histogram",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MetricFamily {
+    help: String,
+    series: BTreeMap<Labels, Metric>,
+}
+
+impl MetricFamily {
+    /// The kind of every series in the family, or `None` before the first is registered.
+    fn kind(&self) -> Option<&'static str> {
+        self.series.values().next().map(Metric::kind)
+    }
+}
+
+/// A metric standing in for one whose name is taken by another kind, so callers can still
+/// update it.
+fn unregistered(name: &str, existing: &str, metric: Metric) -> Metric {
+    warn!(
+        "Metric \"{name}\" is already registered as a {existing}, so this {} won't be exported.",
+        metric.kind()
+    );
+
+    metric
+}
+
+/// Holds every registered metric, keyed by name and then by label set.
+#[derive(Debug, Default)]
+pub struct MetricsRegistry {
+    families: RwLock<BTreeMap<String, MetricFamily>>,
+}
+
+fn to_labels(labels: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = labels
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    labels.sort();
+    labels
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the metric for a name and label set, registering it with `create` if missing.
+    /// If the name is already registered as a different kind of metric, a warning is logged
+    /// and the metric from `create` is returned without being registered, so it's never
+    /// exported.
+    fn get_or_register(
+        &self, name: &str, help: &str, labels: &[(&str, &str)], kind: &'static str,
+        create: impl FnOnce() -> Metric,
+    ) -> Metric {
+        let labels = to_labels(labels);
+
+        if let Some(family) = self.families.read().get(name) {
+            if let Some(existing) = family.kind().filter(|existing| *existing != kind) {
+                return unregistered(name, existing, create());
+            }
+            if let Some(metric) = family.series.get(&labels) {
+                return metric.clone();
+            }
+        }
+
+        let mut families = self.families.write();
+        let family = families
+            .entry(name.to_string())
+            .or_insert_with(|| MetricFamily {
+                help: help.to_string(),
+                series: BTreeMap::new(),
+            });
+
+        // Another thread may have registered the name between the two locks.
+        if let Some(existing) = family.kind().filter(|existing| *existing != kind) {
+            return unregistered(name, existing, create());
+        }
+
+        family.series.entry(labels).or_insert_with(create).clone()
+    }
+
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Counter {
+        match self.get_or_register(name, help, labels, "counter", || {
+            Metric::Counter(Counter::default())
+        }) {
+            Metric::Counter(counter) => counter,
+            other => unreachable!("Metric \"{name}\" is a {}, not a counter.", other.kind()),
+        }
+    }
+
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Gauge {
+        match self.get_or_register(name, help, labels, "gauge", || {
+            Metric::Gauge(Gauge::default())
+        }) {
+            Metric::Gauge(gauge) => gauge,
+            other => unreachable!("Metric \"{name}\" is a {}, not a gauge.", other.kind()),
+        }
+    }
+
+    pub fn histogram(
+        &self, name: &str, help: &str, labels: &[(&str, &str)], buckets: &[f64],
+    ) -> Histogram {
+        match self.get_or_register(name, help, labels, "histogram", || {
+            Metric::Histogram(Histogram::new(buckets))
+        }) {
+            Metric::Histogram(histogram) => histogram,
+            other => unreachable!("Metric \"{name}\" is a {}, not a histogram.", other.kind()),
+        }
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let mut output = String::new();
+
+        for (name, family) in self.families.read().iter() {
+            let Some(kind) = family.kind() else {
+                continue;
+            };
+
+            let _ = writeln!(output, "# HELP {name} {}", escape_help(&family.help));
+            let _ = writeln!(output, "# TYPE {name} {kind}");
+
+            for (labels, metric) in family.series.iter() {
+                match metric {
+                    Metric::Counter(counter) => {
+                        let _ = writeln!(
+                            output,
+                            "{name}{} {}",
+                            format_labels(labels, None),
+                            counter.get()
+                        );
+                    }
+                    Metric::Gauge(gauge) => {
+                        let _ = writeln!(
+                            output,
+                            "{name}{} {}",
+                            format_labels(labels, None),
+                            format_value(gauge.get())
+                        );
+                    }
+                    Metric::Histogram(histogram) => {
+                        render_histogram(&mut output, name, labels, histogram);
+                    }
+                }
+            }
+        }
+
+        output
+    }
+}
+
+fn render_histogram(output: &mut String, name: &str, labels: &Labels, histogram: &Histogram) {
+    let data = &histogram.0;
+    let mut cumulative = 0;
+
+    for (index, bucket) in data.buckets.iter().enumerate() {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let bound = data
+            .bounds
+            .get(index)
+            .map_or("+Inf".to_string(), |bound| format_value(*bound));
+
+        let _ = writeln!(
+            output,
+            "{name}_bucket{} {cumulative}",
+            format_labels(labels, Some(("le", &bound)))
+        );
+    }
+
+    let _ = writeln!(
+        output,
+        "{name}_sum{} {}",
+        format_labels(labels, None),
+        format_value(histogram.sum())
+    );
+    let _ = writeln!(
+        output,
+        "{name}_count{} {}",
+        format_labels(labels, None),
+        histogram.count()
+    );
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &Labels, extra: Option<(&str, &str)>) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .chain(extra)
+        .map(|(key, value)| format!("{key}=\"{}\"", escape_label_value(value)))
+        .collect();
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod metrics_registry_tests {
+    use super::MetricsRegistry;
+
+    #[test]
+    fn counters_and_gauges_render() {
+        let registry = MetricsRegistry::new();
+
+        registry
+            .counter("requests_total", "Requests handled.", &[("outcome", "ok")])
+            .increment(3);
+        registry
+            .gauge("in_flight", "Requests in flight.", &[])
+            .set(2.5);
+
+        let rendered = registry.render_prometheus();
+
+        assert!(rendered.contains("# TYPE requests_total counter\n"));
+        assert!(rendered.contains("requests_total{outcome=\"ok\"} 3\n"));
+        assert!(rendered.contains("in_flight 2.5\n"));
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let registry = MetricsRegistry::new();
+        let histogram = registry.histogram("duration_seconds", "Durations.", &[], &[0.1, 1.0]);
+
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(5.0);
+
+        let rendered = registry.render_prometheus();
+
+        assert!(rendered.contains("duration_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(rendered.contains("duration_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(rendered.contains("duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(rendered.contains("duration_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn same_labels_share_a_series() {
+        let registry = MetricsRegistry::new();
+
+        registry
+            .counter("hits_total", "Hits.", &[("a", "1"), ("b", "2")])
+            .increment(1);
+        let counter = registry.counter("hits_total", "Hits.", &[("b", "2"), ("a", "1")]);
+
+        assert_eq!(1, counter.get());
+    }
+
+    #[test]
+    fn a_name_keeps_its_first_kind() {
+        let registry = MetricsRegistry::new();
+
+        registry
+            .counter("jobs", "Jobs.", &[("queue", "a")])
+            .increment(1);
+        registry.gauge("jobs", "Jobs.", &[("queue", "b")]).set(4.0);
+
+        let rendered = registry.render_prometheus();
+
+        assert!(rendered.contains("# TYPE jobs counter\n"));
+        assert!(!rendered.contains("queue=\"b\""));
+    }
+}
@@ -0,0 +1,153 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+use utils::core_types::{CoreError, CoreResult};
+
+use crate::registry::MetricsRegistry;
+
+/// How long a client gets to send its request before the connection is dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Waits after a failed accept, doubling while failures continue, so errors such as running
+/// out of file descriptors don't spin.
+const ACCEPT_RETRY_INITIAL: Duration = Duration::from_millis(10);
+const ACCEPT_RETRY_MAX: Duration = Duration::from_secs(1);
+
+/// A running Prometheus exposition endpoint. The server stops when this is dropped.
+#[derive(Debug)]
+pub struct MetricsServer {
+    pub address: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Serve `GET /metrics` in the Prometheus text format on `address`.
+/// Bind to port 0 to have the OS pick a free port, available from the returned server.
+pub async fn serve(
+    address: SocketAddr, registry: Arc<MetricsRegistry>,
+) -> CoreResult<MetricsServer> {
+    let listener = TcpListener::bind(address).await.map_err(CoreError::from)?;
+    let address = listener.local_addr().map_err(CoreError::from)?;
+
+    debug!("Serving metrics on http://{address}/metrics");
+
+    let handle = tokio::spawn(async move {
+        let mut retry_delay = ACCEPT_RETRY_INITIAL;
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    retry_delay = ACCEPT_RETRY_INITIAL;
+                    let registry = registry.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream, &registry).await {
+                            debug!("Failed to answer metrics request: {e}");
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("Failed to accept metrics connection: {e}");
+                    tokio::time::sleep(retry_delay).await;
+                    retry_delay = (retry_delay * 2).min(ACCEPT_RETRY_MAX);
+                }
+            }
+        }
+    });
+
+    Ok(MetricsServer { address, handle })
+}
+
+async fn respond(mut stream: TcpStream, registry: &MetricsRegistry) -> std::io::Result<()> {
+    // Only the request line matters, which fits comfortably in one read.
+    let mut buffer = [0; 1024];
+    let read = tokio::time::timeout(READ_TIMEOUT, stream.read(&mut buffer))
+        .await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let request_line = request.lines().next().unwrap_or_default();
+
+    let (status, content_type, body) =
+        match request_line.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["GET", "/metrics", ..] => (
+                "200 OK",
+                "text/plain; version=0.0.4; charset=utf-8",
+                registry.render_prometheus(),
+            ),
+            ["GET", ..] => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain",
+                "Method Not Allowed\n".to_string(),
+            ),
+        };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod metrics_server_tests {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::serve;
+    use crate::registry::MetricsRegistry;
+
+    #[tokio::test]
+    async fn serves_prometheus_text() {
+        let registry = Arc::new(MetricsRegistry::new());
+        registry
+            .counter("served_total", "Served.", &[])
+            .increment(1);
+
+        let server = serve("127.0.0.1:0".parse().unwrap(), registry)
+            .await
+            .unwrap();
+
+        let mut stream = TcpStream::connect(server.address).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("served_total 1\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_clients_are_dropped() {
+        let server = serve(
+            "127.0.0.1:0".parse().unwrap(),
+            Arc::new(MetricsRegistry::new()),
+        )
+        .await
+        .unwrap();
+
+        // Connects without sending a request, then waits to be hung up on.
+        let mut stream = TcpStream::connect(server.address).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+
+        assert!(response.is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+/// Delays before retrying, doubling from `initial` up to `max`. Each is spread by up to
+/// `jitter` (a fraction of the delay) either way, so work failing together doesn't retry
+/// together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub jitter: f64,
+}
+
+impl Backoff {
+    /// The delay before the `retry`th retry in a row, counting from 0, given a random
+    /// number from 0 to 1.
+    pub fn delay(&self, retry: u32, random: f64) -> Duration {
+        let base = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max);
+
+        base.mul_f64((1.0 + self.jitter * (2.0 * random - 1.0)).max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod backoff_tests {
+    use std::time::Duration;
+
+    use super::Backoff;
+
+    #[test]
+    fn backoff_doubles_up_to_the_max_with_jitter() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            jitter: 0.5,
+        };
+
+        assert_eq!(Duration::from_millis(100), backoff.delay(0, 0.5));
+        assert_eq!(Duration::from_millis(400), backoff.delay(2, 0.5));
+        assert_eq!(Duration::from_secs(1), backoff.delay(10, 0.5));
+        assert_eq!(Duration::from_millis(50), backoff.delay(0, 0.0));
+        assert_eq!(Duration::from_millis(150), backoff.delay(0, 1.0));
+    }
+}
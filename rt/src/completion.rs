@@ -0,0 +1,352 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use clap_complete::{env::EnvCompleter, generate, Generator, Shell};
+use clap_complete_nushell::Nushell;
+use cli::{
+    completion_command, CompletionShell, CompletionSubCommand, ShellSelection,
+    DYNAMIC_COMPLETION_VAR,
+};
+use serde_json::json;
+use tracing::info;
+use utils::{
+    core_types::{CoreError, CoreResult},
+    project_name_str,
+};
+
+use crate::output::{record, CommandOutput};
+
+pub fn run(command: CompletionSubCommand, dynamic: bool) -> CoreResult<CommandOutput> {
+    match command {
+        CompletionSubCommand::Install { shell } => {
+            install(resolve(shell)?, dynamic, &InstallDirs::from_env())
+        }
+        CompletionSubCommand::Uninstall { shell } => {
+            uninstall(resolve(shell)?, &InstallDirs::from_env())
+        }
+        subcommand => {
+            if let Some(shell) = subcommand.shell() {
+                write_script(shell, dynamic, &mut std::io::stdout())?;
+            }
+
+            // The script is the output, whatever the format.
+            Ok(CommandOutput::written())
+        }
+    }
+}
+
+/// Write the completion script for `shell`. A dynamic script completes values by running
+/// the binary, while a static one only knows what the command definitions say.
+pub fn write_script(
+    shell: CompletionShell, dynamic: bool, writer: &mut dyn Write,
+) -> CoreResult<()> {
+    if dynamic {
+        return write_dynamic_script(shell, writer);
+    }
+
+    match shell {
+        CompletionShell::Bash => write_with(Shell::Bash, writer),
+        CompletionShell::Zsh => write_with(Shell::Zsh, writer),
+        CompletionShell::Fish => write_with(Shell::Fish, writer),
+        CompletionShell::PowerShell => write_with(Shell::PowerShell, writer),
+        CompletionShell::Elvish => write_with(Shell::Elvish, writer),
+        CompletionShell::Nushell => write_with(Nushell, writer),
+    }
+
+    Ok(())
+}
+
+fn write_dynamic_script(shell: CompletionShell, writer: &mut dyn Write) -> CoreResult<()> {
+    use clap_complete::env::{Bash, Elvish, Fish, Powershell, Zsh};
+
+    let completer: &dyn EnvCompleter = match shell {
+        CompletionShell::Bash => &Bash,
+        CompletionShell::Zsh => &Zsh,
+        CompletionShell::Fish => &Fish,
+        CompletionShell::PowerShell => &Powershell,
+        CompletionShell::Elvish => &Elvish,
+        CompletionShell::Nushell => {
+            return Err(CoreError::GeneralTaskError(
+                "Dynamic completions aren't available for Nushell yet.".to_string(),
+            )
+            .into())
+        }
+    };
+
+    let name = project_name_str!();
+    completer
+        .write_registration(DYNAMIC_COMPLETION_VAR, name, name, name, writer)
+        .map_err(|e| CoreError::from(e).into())
+}
+
+fn write_with(generator: impl Generator, writer: &mut dyn Write) {
+    generate(
+        generator,
+        &mut completion_command(),
+        project_name_str!(),
+        writer,
+    );
+}
+
+fn resolve(selection: ShellSelection) -> CoreResult<CompletionShell> {
+    match selection {
+        ShellSelection::Shell(shell) => Ok(shell),
+        ShellSelection::Auto => detect_shell().ok_or_else(|| {
+            CoreError::GeneralTaskError(
+                "Could not detect the shell from $SHELL. Pass it with --shell.".to_string(),
+            )
+            .into()
+        }),
+    }
+}
+
+/// The user's shell, going by the name of the program in `$SHELL`.
+fn detect_shell() -> Option<CompletionShell> {
+    let shell = PathBuf::from(std::env::var_os("SHELL")?);
+    let name = shell.file_stem()?.to_str()?;
+
+    shell_from_program(name)
+}
+
+fn shell_from_program(name: &str) -> Option<CompletionShell> {
+    match name {
+        "bash" => Some(CompletionShell::Bash),
+        "zsh" => Some(CompletionShell::Zsh),
+        "fish" => Some(CompletionShell::Fish),
+        "pwsh" | "powershell" => Some(CompletionShell::PowerShell),
+        "elvish" => Some(CompletionShell::Elvish),
+        "nu" => Some(CompletionShell::Nushell),
+        _ => None,
+    }
+}
+
+/// The directories completion scripts are installed under, read from the environment once.
+#[derive(Debug, Default)]
+struct InstallDirs {
+    home: Option<PathBuf>,
+    /// The platform's config directory, such as `~/Library/Application Support` on macOS.
+    config: Option<PathBuf>,
+    /// Bash-completion and fish follow the XDG variables on every platform.
+    xdg_data_home: Option<PathBuf>,
+    xdg_config_home: Option<PathBuf>,
+    zdotdir: Option<PathBuf>,
+}
+
+impl InstallDirs {
+    fn from_env() -> Self {
+        let var = |name| {
+            std::env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        };
+
+        Self {
+            home: dirs::home_dir(),
+            config: dirs::config_dir(),
+            xdg_data_home: var("XDG_DATA_HOME"),
+            xdg_config_home: var("XDG_CONFIG_HOME"),
+            zdotdir: var("ZDOTDIR"),
+        }
+    }
+}
+
+/// Where each shell looks for completion scripts, with what the user has to add to their
+/// shell config for the shells that don't load them automatically.
+fn install_location(
+    shell: CompletionShell, dirs: &InstallDirs,
+) -> CoreResult<(PathBuf, Option<String>)> {
+    let missing =
+        |dir: &str| CoreError::GeneralTaskError(format!("Could not find the {dir} directory."));
+    let home = || dirs.home.clone().ok_or_else(|| missing("home"));
+    let config = || dirs.config.clone().ok_or_else(|| missing("config"));
+    let xdg = |dir: &Option<PathBuf>, fallback: &str| match dir {
+        Some(dir) => Ok(dir.clone()),
+        None => home().map(|home| home.join(fallback)),
+    };
+    let name = project_name_str!();
+
+    let location = match shell {
+        CompletionShell::Bash => (
+            xdg(&dirs.xdg_data_home, ".local/share")?
+                .join("bash-completion/completions")
+                .join(name),
+            None,
+        ),
+        CompletionShell::Zsh => {
+            let dir = match &dirs.zdotdir {
+                Some(zdotdir) => zdotdir.clone(),
+                None => home()?,
+            }
+            .join(".zfunc");
+            let hint = format!(
+                "fpath+=(\"{}\"); autoload -Uz compinit && compinit",
+                dir.display()
+            );
+            (dir.join(format!("_{name}")), Some(hint))
+        }
+        CompletionShell::Fish => (
+            xdg(&dirs.xdg_config_home, ".config")?
+                .join("fish/completions")
+                .join(format!("{name}.fish")),
+            None,
+        ),
+        CompletionShell::PowerShell => {
+            let path = config()?
+                .join("powershell/completions")
+                .join(format!("{name}.ps1"));
+            let hint = format!(". \"{}\"", path.display());
+            (path, Some(hint))
+        }
+        CompletionShell::Elvish => (
+            config()?.join("elvish/lib").join(format!("{name}.elv")),
+            Some(format!("use {name}")),
+        ),
+        CompletionShell::Nushell => {
+            let path = config()?
+                .join("nushell/completions")
+                .join(format!("{name}.nu"));
+            let hint = format!("source \"{}\"", path.display());
+            (path, Some(hint))
+        }
+    };
+
+    Ok(location)
+}
+
+fn install(shell: CompletionShell, dynamic: bool, dirs: &InstallDirs) -> CoreResult<CommandOutput> {
+    let (path, hint) = install_location(shell, dirs)?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(CoreError::from)?;
+    }
+
+    let mut script = Vec::new();
+    write_script(shell, dynamic, &mut script)?;
+    std::fs::write(&path, script).map_err(CoreError::from)?;
+
+    let message = format!("Installed {shell} completions to \"{}\".", path.display());
+    info!("{message}");
+
+    let mut text = vec![message];
+    if let Some(hint) = &hint {
+        text.push(format!(
+            "Add this to your shell config to load them: {hint}"
+        ));
+    }
+
+    Ok(CommandOutput::record(record(json!({
+        "shell": shell.to_string(),
+        "path": path.display().to_string(),
+        "config_line": hint,
+        "dynamic": dynamic,
+    })))
+    .with_text(text))
+}
+
+fn uninstall(shell: CompletionShell, dirs: &InstallDirs) -> CoreResult<CommandOutput> {
+    let (path, _) = install_location(shell, dirs)?;
+    let removed = remove_if_present(&path)?;
+
+    let text = if removed {
+        format!("Removed {shell} completions from \"{}\".", path.display())
+    } else {
+        format!("No {shell} completions found at \"{}\".", path.display())
+    };
+    info!("{text}");
+
+    Ok(CommandOutput::record(record(json!({
+        "shell": shell.to_string(),
+        "path": path.display().to_string(),
+        "removed": removed,
+    })))
+    .with_text(vec![text]))
+}
+
+fn remove_if_present(path: &Path) -> CoreResult<bool> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(CoreError::from(e).into()),
+    }
+}
+
+#[cfg(test)]
+mod completion_tests {
+    use clap::ValueEnum;
+    use cli::CompletionShell;
+
+    use super::{
+        install, install_location, shell_from_program, uninstall, write_script, InstallDirs,
+    };
+
+    #[test]
+    fn every_shell_gets_a_script() {
+        for shell in CompletionShell::value_variants() {
+            let mut script = Vec::new();
+            write_script(*shell, false, &mut script).unwrap();
+
+            let script = String::from_utf8(script).unwrap();
+            assert!(
+                script.contains("demo-tasks"),
+                "{shell} script is incomplete"
+            );
+        }
+
+        let mut script = Vec::new();
+        write_script(CompletionShell::Bash, true, &mut script).unwrap();
+        assert!(String::from_utf8(script).unwrap().contains("COMPLETE"));
+
+        assert_eq!(Some(CompletionShell::Nushell), shell_from_program("nu"));
+        assert_eq!(None, shell_from_program("sh"));
+    }
+
+    #[test]
+    fn install_locations_follow_the_environment() {
+        let dirs = InstallDirs {
+            home: Some("/home/user".into()),
+            ..Default::default()
+        };
+
+        let (bash, _) = install_location(CompletionShell::Bash, &dirs).unwrap();
+        assert!(bash.starts_with("/home/user/.local/share/bash-completion/completions"));
+        let (zsh, hint) = install_location(CompletionShell::Zsh, &dirs).unwrap();
+        assert!(zsh.starts_with("/home/user/.zfunc"));
+        assert!(hint.unwrap().starts_with("fpath+=(\"/home/user/.zfunc\")"));
+
+        let dirs = InstallDirs {
+            xdg_data_home: Some("/data".into()),
+            zdotdir: Some("/zdot".into()),
+            ..dirs
+        };
+
+        let (bash, _) = install_location(CompletionShell::Bash, &dirs).unwrap();
+        assert!(bash.starts_with("/data/bash-completion/completions"));
+        let (zsh, hint) = install_location(CompletionShell::Zsh, &dirs).unwrap();
+        assert!(zsh.starts_with("/zdot/.zfunc"));
+        assert!(hint.unwrap().starts_with("fpath+=(\"/zdot/.zfunc\")"));
+    }
+
+    #[test]
+    fn install_writes_the_script_and_uninstall_removes_it() {
+        let dir = std::env::temp_dir().join(format!("completion-test-{}", std::process::id()));
+        let dirs = InstallDirs {
+            xdg_config_home: Some(dir.clone()),
+            ..Default::default()
+        };
+        let (path, _) = install_location(CompletionShell::Fish, &dirs).unwrap();
+
+        install(CompletionShell::Fish, false, &dirs).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("demo-tasks"));
+
+        uninstall(CompletionShell::Fish, &dirs).unwrap();
+        assert!(!path.exists());
+        // Nothing left to remove is not an error.
+        uninstall(CompletionShell::Fish, &dirs).unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgAction, Command};
+use clap_mangen::Man;
+use utils::core_types::{CoreError, CoreResult};
+
+/// Write a man page for `command` and for each of its subcommands into `out_dir`.
+/// Returns the pages written, parents before their subcommands.
+pub fn write_man_pages(command: Command, out_dir: &Path) -> CoreResult<Vec<PathBuf>> {
+    std::fs::create_dir_all(out_dir).map_err(CoreError::from)?;
+
+    let mut written = Vec::new();
+    write_man_page(built(command), out_dir, &mut written)?;
+
+    Ok(written)
+}
+
+fn write_man_page(command: Command, out_dir: &Path, written: &mut Vec<PathBuf>) -> CoreResult<()> {
+    written.push(
+        Man::new(command.clone())
+            .generate_to(out_dir)
+            .map_err(CoreError::from)?,
+    );
+
+    for subcommand in command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+    {
+        write_man_page(subcommand.clone(), out_dir, written)?;
+    }
+
+    Ok(())
+}
+
+/// A Markdown reference for `command` and every subcommand, one section each.
+pub fn markdown(command: Command) -> String {
+    let command = built(command);
+    let mut out = String::new();
+    write_markdown(&command, true, &mut out);
+
+    out
+}
+
+/// Build the command so subcommands know their full names and inherit global arguments.
+fn built(command: Command) -> Command {
+    let mut command = command.disable_help_subcommand(true);
+    command.build();
+    command
+}
+
+fn write_markdown(command: &Command, is_root: bool, out: &mut String) {
+    let name = full_name(command);
+    let heading = if is_root { "#" } else { "##" };
+    out.push_str(&format!("{heading} `{name}`\n\n"));
+
+    if let Some(about) = command.get_long_about().or(command.get_about()) {
+        out.push_str(&format!("{about}\n\n"));
+    }
+
+    let usage = command.clone().render_usage().to_string();
+    let usage = usage.trim_start_matches("Usage:").trim();
+    out.push_str(&format!("```text\n{usage}\n```\n\n"));
+
+    let positionals: Vec<&Arg> = command
+        .get_positionals()
+        .filter(|arg| !arg.is_hide_set())
+        .collect();
+    write_args("Arguments", &positionals, out);
+
+    // Global options are listed once, under the root command.
+    let options: Vec<&Arg> = command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .filter(|arg| is_root || !(arg.is_global_set() || is_builtin(arg)))
+        .collect();
+    write_args("Options", &options, out);
+
+    let subcommands: Vec<&Command> = command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .collect();
+
+    if !subcommands.is_empty() {
+        out.push_str("**Subcommands:**\n\n");
+        for subcommand in &subcommands {
+            let name = full_name(subcommand);
+            out.push_str(&format!("* [`{name}`](#{})", name.replace(' ', "-")));
+            if let Some(about) = subcommand.get_about() {
+                out.push_str(&format!(": {about}"));
+            }
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+
+    for subcommand in subcommands {
+        write_markdown(subcommand, false, out);
+    }
+}
+
+/// The help and version flags clap adds to every command.
+fn is_builtin(arg: &Arg) -> bool {
+    matches!(
+        arg.get_action(),
+        ArgAction::Help | ArgAction::HelpShort | ArgAction::HelpLong | ArgAction::Version
+    )
+}
+
+fn write_args(title: &str, args: &[&Arg], out: &mut String) {
+    if args.is_empty() {
+        return;
+    }
+
+    out.push_str(&format!("**{title}:**\n\n"));
+
+    for arg in args {
+        out.push_str(&format!("* `{}`", arg_usage(arg)));
+        if let Some(help) = arg.get_help() {
+            // Clap drops the full stop from the end of doc comments.
+            let help = help.to_string();
+            let stop = if help.ends_with(['.', '!', '?']) {
+                ""
+            } else {
+                "."
+            };
+            out.push_str(&format!(": {help}{stop}"));
+        }
+
+        // Flags default to off, which isn't worth saying.
+        let defaults: Vec<String> = arg
+            .get_default_values()
+            .iter()
+            .filter(|_| arg.get_action().takes_values())
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect();
+        if !defaults.is_empty() {
+            out.push_str(&format!(" Default: `{}`.", defaults.join(", ")));
+        }
+
+        let possible: Vec<String> = arg
+            .get_possible_values()
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| format!("`{}`", value.get_name()))
+            .collect();
+        if !possible.is_empty() {
+            out.push_str(&format!(" One of {}.", possible.join(", ")));
+        }
+
+        out.push('\n');
+    }
+
+    out.push('\n');
+}
+
+/// How an argument is written on the command line, such as `-c, --config <FILE>`.
+fn arg_usage(arg: &Arg) -> String {
+    let value = arg
+        .get_value_names()
+        .map(|names| {
+            names
+                .iter()
+                .map(|name| format!("<{name}>"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_else(|| format!("<{}>", arg.get_id().as_str().to_uppercase()));
+
+    if arg.is_positional() {
+        return value;
+    }
+
+    let flags = [
+        arg.get_short().map(|short| format!("-{short}")),
+        arg.get_long().map(|long| format!("--{long}")),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(", ");
+
+    if arg.get_action().takes_values() {
+        format!("{flags} {value}")
+    } else {
+        flags
+    }
+}
+
+fn full_name(command: &Command) -> String {
+    command
+        .get_bin_name()
+        .unwrap_or(command.get_name())
+        .to_string()
+}
+
+#[cfg(test)]
+mod docs_tests {
+    use super::{markdown, write_man_pages};
+
+    #[test]
+    fn every_command_is_documented() {
+        let reference = markdown(cli::get_command());
+        let dir = std::env::temp_dir().join(format!("docs-test-{}", std::process::id()));
+        let pages = write_man_pages(cli::get_command(), &dir).unwrap();
+        let first_page = std::fs::read_to_string(&pages[0]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(reference.starts_with("# `rust-starter-template`"));
+        assert!(reference.contains("## `rust-starter-template logs tail`"));
+        assert!(reference.contains("* `-n, --lines <LINES>`: The number of lines to print."));
+        assert_eq!(1, reference.matches("* `-c, --config <FILE>`").count());
+        assert!(pages
+            .iter()
+            .any(|page| page.ends_with("rust-starter-template-logs-tail.1")));
+        assert!(first_page.starts_with(".ie \\n(.g .ds Aq"));
+        assert!(first_page.contains(".TH rust-starter-template 1"));
+    }
+}
@@ -1,68 +1,284 @@
-use std::rc::Weak;
+use std::{
+    rc::Weak,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use clap_complete::generate;
-use cli::{get_command, AppCommand};
-use configuration::app_config::AppConfig;
+use cli::{get_command, AppCommand, OutputFormat, RestartPolicyArg};
+use configuration::{
+    app_config::{self, AppConfig},
+    AppConfigManager,
+};
+use log_config::CommandLineLogging;
+use logs::LogDirectory;
+use output::{record, CommandOutput};
 use parking_lot::RwLock;
-use tokio::{fs::File, io::AsyncReadExt, task::JoinSet};
-use tracing::{debug, error, info, instrument, Instrument};
-use utils::{core_types::CoreResult, logging::LoggingManager, project_name_str};
+use pool::WorkerPool;
+use serde_json::json;
+use shutdown::Shutdown;
+use supervisor::{RestartPolicy, RestartStrategy, Supervisor};
+use tasks::TaskRegistry;
+use tokio::{fs::File, io::AsyncReadExt};
+use tracing::{debug, error, info, instrument, level_filters::LevelFilter, Instrument};
+use utils::{
+    core_types::{CoreError, CoreResult},
+    logging::{redaction::Redactor, LoggingManager},
+    random::Xorshift,
+};
+
+pub mod backoff;
+pub mod completion;
+pub mod docs;
+pub mod log_config;
+pub mod logs;
+pub mod output;
+pub mod plugins;
+pub mod pool;
+pub mod script;
+pub mod shell;
+pub mod shutdown;
+pub mod supervisor;
+pub mod tasks;
+#[cfg(feature = "tui")]
+pub mod tui;
 
 #[derive(Debug)]
 pub struct AppRuntime {
     pub log_manager: Weak<LoggingManager>,
     pub app_config: RwLock<AppConfig>,
+    /// How command results are written to stdout.
+    pub output_format: OutputFormat,
+    /// The tasks commands have spawned, for watching them run.
+    pub tasks: Arc<TaskRegistry>,
+    /// Tells commands when to wind down.
+    pub shutdown: Shutdown,
+    /// Restarts long-running background tasks when they stop.
+    pub supervisor: Supervisor,
+    /// The command line's logging options, applied over the config again when it's reloaded.
+    pub command_line_logging: CommandLineLogging,
 }
 
 impl AppRuntime {
     pub fn new(log_manager: Weak<LoggingManager>, app_config: AppConfig) -> Self {
+        let tasks: Arc<TaskRegistry> = Arc::default();
+        let shutdown = Shutdown::default();
+
         Self {
             log_manager,
             app_config: RwLock::new(app_config),
+            output_format: OutputFormat::default(),
+            supervisor: Supervisor::new(shutdown.clone(), tasks.clone()),
+            tasks,
+            shutdown,
+            command_line_logging: CommandLineLogging::default(),
         }
     }
 
-    #[instrument(skip(self), fields(command))]
+    pub fn with_output_format(mut self, format: OutputFormat) -> Self {
+        self.output_format = format;
+        self
+    }
+
+    /// Use `shutdown` in place of the runtime's own. Set before supervising any tasks, as
+    /// the supervisor is replaced along with it.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.supervisor = Supervisor::new(shutdown.clone(), self.tasks.clone());
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn with_command_line_logging(mut self, command_line_logging: CommandLineLogging) -> Self {
+        self.command_line_logging = command_line_logging;
+        self
+    }
+
+    /// Apply the current config's logging settings to the running sinks, as after a reload.
+    pub fn reload_logging(&self) -> CoreResult<()> {
+        let Some(log_manager) = self.log_manager.upgrade() else {
+            return Ok(());
+        };
+
+        let configured = log_config::configure(
+            LoggingManager::new(),
+            &self.app_config.read(),
+            &self.command_line_logging,
+        )?;
+        log_manager.reconfigure(configured)
+    }
+
     pub async fn enter(&self, command: AppCommand) -> CoreResult<()> {
+        self.enter_with_output(command, self.output_format).await
+    }
+
+    /// Run `command`, writing its result in `output_format` rather than the runtime's.
+    pub async fn enter_with_output(
+        &self, command: AppCommand, output_format: OutputFormat,
+    ) -> CoreResult<()> {
+        self.execute(command, output_format)
+            .await?
+            .write(output_format, &mut std::io::stdout())
+    }
+
+    /// Run `command` and return its result instead of writing it, for callers that show
+    /// results their own way. `output_format` only matters to commands that stream output.
+    #[instrument(name = "enter", skip(self, output_format), fields(command))]
+    pub async fn execute(
+        &self, command: AppCommand, output_format: OutputFormat,
+    ) -> CoreResult<CommandOutput> {
         info!("Executing command \"{command}\".");
-        match command {
-            AppCommand::TasksDemo { num_tasks } => {
-                self.test_tasks(num_tasks).await?;
+        let command_name = command.to_string();
+        let metric_name = command.name();
+        let started = Instant::now();
+
+        let result = self.dispatch(command, output_format).await;
+
+        metrics::histogram(
+            "app_command_duration_seconds",
+            "Time taken to run a command.",
+            &[("command", metric_name)],
+            metrics::DEFAULT_DURATION_BUCKETS,
+        )
+        .observe(started.elapsed().as_secs_f64());
+        metrics::counter(
+            "app_commands_total",
+            "Commands run, by outcome.",
+            &[
+                ("command", metric_name),
+                (
+                    "outcome",
+                    if result.is_ok() { "success" } else { "failure" },
+                ),
+            ],
+        )
+        .increment(1);
+
+        if let Err(e) = &result {
+            error!("Command \"{command_name}\" failed: {e}");
+        }
+
+        result
+    }
+
+    async fn dispatch(
+        &self, command: AppCommand, output_format: OutputFormat,
+    ) -> CoreResult<CommandOutput> {
+        let output = match command {
+            AppCommand::TasksDemo {
+                num_tasks,
+                concurrency,
+            } => {
+                self.test_tasks(num_tasks, concurrency.map(|jobs| jobs as usize))
+                    .await?
+            }
+            AppCommand::FileError => self.test_errors().await?,
+            AppCommand::WorkerDemo {
+                workers,
+                failure_rate,
+                policy,
+                detach,
+            } => {
+                self.demo_workers(workers, failure_rate, policy, detach)
+                    .await?
             }
-            AppCommand::FileError => {
-                self.test_errors().await?;
+            AppCommand::Status => self.supervisor_status(),
+            AppCommand::Completion {
+                subcommand,
+                dynamic,
+            } => completion::run(subcommand, dynamic)?,
+            AppCommand::Logs { subcommand } => {
+                let directory = {
+                    let config = self.app_config.read();
+                    LogDirectory::new(
+                        &config.logging.rolling_log_path,
+                        &config.logging.rolling_log_prefix,
+                    )
+                };
+
+                logs::run(&directory, subcommand, output_format, self.shutdown.token()).await?
             }
-            AppCommand::Completion { subcommand } => {
-                let mut app = get_command();
-
-                match subcommand {
-                    cli::CompletionSubCommand::Bash => generate(
-                        clap_complete::shells::Bash,
-                        &mut app,
-                        project_name_str!(),
-                        &mut std::io::stdout(),
-                    ),
-                    cli::CompletionSubCommand::Zsh => generate(
-                        clap_complete::shells::Zsh,
-                        &mut app,
-                        project_name_str!(),
-                        &mut std::io::stdout(),
-                    ),
-                    cli::CompletionSubCommand::Fish => generate(
-                        clap_complete::shells::Fish,
-                        &mut app,
-                        project_name_str!(),
-                        &mut std::io::stdout(),
-                    ),
+            AppCommand::Config { subcommand } => match subcommand {
+                cli::ConfigSubCommand::Sources => config_sources(),
+                cli::ConfigSubCommand::Get { key } => config_get(&key)?,
+                cli::ConfigSubCommand::Database { name, show_secrets } => {
+                    self.config_database(&name, show_secrets)?
                 }
+            },
+            AppCommand::Shell => shell::run(self).await?,
+            #[cfg(feature = "tui")]
+            AppCommand::Tui { command } => tui::run(self, command).await?,
+            AppCommand::RunScript {
+                file,
+                continue_on_error,
+                ..
+            } => script::run(self, &file, continue_on_error, output_format).await?,
+            AppCommand::Plugin(args) => {
+                let log_level = self
+                    .log_manager
+                    .upgrade()
+                    .and_then(|log_manager| log_manager.sink_level(utils::logging::FMT_SINK))
+                    .unwrap_or(LevelFilter::OFF)
+                    .to_string()
+                    .to_lowercase();
+                let config = serde_json::to_value(&*self.app_config.read())
+                    .map_err(|e| CoreError::GeneralTaskError(e.to_string()))?;
+
+                plugins::run(args, &config, &log_level).await?
             }
+            AppCommand::Docs { subcommand } => match subcommand {
+                cli::DocsSubCommand::Man { out_dir } => {
+                    let pages = docs::write_man_pages(get_command(), &out_dir)?;
+                    info!(
+                        "Wrote {} man pages to \"{}\".",
+                        pages.len(),
+                        out_dir.display()
+                    );
+
+                    CommandOutput::table(
+                        pages
+                            .iter()
+                            .map(|page| record(json!({ "path": page.display().to_string() })))
+                            .collect(),
+                    )
+                }
+                cli::DocsSubCommand::Markdown => {
+                    // Like completion scripts, the reference is the output whatever the format.
+                    print!("{}", docs::markdown(get_command()));
+
+                    CommandOutput::written()
+                }
+            },
+        };
+
+        Ok(output)
+    }
+
+    fn config_database(&self, name: &str, show_secrets: bool) -> CoreResult<CommandOutput> {
+        let config = self.app_config.read();
+        let database = config
+            .databases
+            .iter()
+            .find(|database| database.name == name)
+            .ok_or_else(|| {
+                CoreError::GeneralTaskError(format!("No database named \"{name}\" is configured."))
+            })?;
+
+        let mut database = json!({
+            "name": database.name,
+            "url": database.url,
+        });
+        if !show_secrets {
+            output::redact(
+                &mut database,
+                &redactor_from_config(&config.logging.redaction)?,
+                &["database"],
+            );
         }
 
-        Ok(())
+        Ok(CommandOutput::record(record(database)))
     }
 
     #[instrument(skip(self))]
-    async fn test_errors(&self) -> CoreResult<()> {
+    async fn test_errors(&self) -> CoreResult<CommandOutput> {
         debug!("Opening file.");
 
         let mut found_file = File::open("non-existent-file").await?;
@@ -74,31 +290,261 @@ impl AppRuntime {
 
         info!("Read {byte_count} bytes.");
 
-        Ok(())
+        Ok(CommandOutput::record(record(
+            json!({ "bytes": byte_count }),
+        )))
+    }
+
+    /// Supervise workers that each fail now and then, until shutdown or the supervisor gives
+    /// up on one.
+    #[instrument(skip(self))]
+    async fn demo_workers(
+        &self, workers: usize, failure_rate: f64, policy: Option<RestartPolicyArg>, detach: bool,
+    ) -> CoreResult<CommandOutput> {
+        let mut strategy = RestartStrategy::from_config(&self.app_config.read().supervisor)?;
+        if let Some(policy) = policy {
+            strategy = strategy.with_policy(match policy {
+                RestartPolicyArg::Never => RestartPolicy::Never,
+                RestartPolicyArg::OnFailure => RestartPolicy::OnFailure,
+                RestartPolicyArg::Always => RestartPolicy::Always,
+            });
+        }
+
+        for i in 0..workers {
+            self.supervisor.spawn(
+                format!("demo worker {i}"),
+                strategy,
+                move |token| async move {
+                    let random = Xorshift::new();
+
+                    loop {
+                        tokio::select! {
+                            _ = tokio::time::sleep(Duration::from_millis(500)) => {}
+                            _ = token.cancelled() => return Ok(()),
+                        }
+
+                        if random.next_f64() < failure_rate {
+                            return Err(CoreError::GeneralTaskError(format!(
+                                "Demo worker {i} hit a simulated failure."
+                            ))
+                            .into());
+                        }
+                        debug!("Demo worker {i} did some work.");
+                    }
+                },
+            )?;
+        }
+
+        if !detach {
+            self.supervisor.wait().await?;
+        }
+
+        Ok(self.supervisor_status())
+    }
+
+    fn supervisor_status(&self) -> CommandOutput {
+        let tasks = self.supervisor.snapshot();
+
+        let rows = tasks
+            .iter()
+            .map(|task| {
+                record(json!({
+                    "name": task.name,
+                    "state": task.state.to_string(),
+                    "policy": task.policy.to_string(),
+                    "restarts": task.restarts,
+                    "since_ms": task.since.elapsed().as_millis() as u64,
+                    "last_error": task.last_error,
+                }))
+            })
+            .collect();
+        let text = if tasks.is_empty() {
+            vec!["No tasks are supervised.".to_string()]
+        } else {
+            tasks
+                .iter()
+                .map(|task| {
+                    let error = task
+                        .last_error
+                        .as_ref()
+                        .map(|error| format!(" Last error: {error}"))
+                        .unwrap_or_default();
+
+                    format!(
+                        "{}: {} for {}s, restarted {} times ({}).{error}",
+                        task.name,
+                        task.state,
+                        task.since.elapsed().as_secs(),
+                        task.restarts,
+                        task.policy
+                    )
+                })
+                .collect()
+        };
+
+        CommandOutput::table(rows).with_text(text)
     }
 
     #[instrument(skip(self), fields(task_count))]
-    async fn test_tasks(&self, task_count: usize) -> CoreResult<()> {
-        let mut task_tracker: JoinSet<String> = JoinSet::new();
+    async fn test_tasks(
+        &self, task_count: usize, concurrency: Option<usize>,
+    ) -> CoreResult<CommandOutput> {
+        let mut pool = WorkerPool::from_config(&self.app_config.read().pool)
+            .with_ordered(true)
+            .with_shutdown(self.shutdown.token())
+            .with_tracking(self.tasks.clone(), "demo task")
+            .with_progress(|progress| {
+                if let Some(total) = progress.total.filter(|total| *total >= 4) {
+                    if progress.done() % (total / 4) == 0 {
+                        info!("{} of {total} demo tasks done.", progress.done());
+                    }
+                }
+            });
+        if let Some(concurrency) = concurrency {
+            pool = pool.with_concurrency(concurrency);
+        }
 
-        for i in 0..task_count {
-            task_tracker.spawn(
+        let report = pool
+            .run(0..task_count, |i| {
                 async move {
                     debug!("Entered task {i}");
 
-                    format!("Hello from task {i}")
+                    Ok((i, format!("Hello from task {i}")))
                 }
-                .instrument(tracing::trace_span!("test_tasks_task")),
-            );
-        }
+                .instrument(tracing::trace_span!("test_tasks_task"))
+            })
+            .await;
 
-        while let Some(task_result) = task_tracker.join_next().await {
-            match task_result {
-                Ok(msg) => info!("Received msg \"{}\"", msg),
-                Err(e) => error!("Failed to process task: {}", e),
-            }
+        let tasks_completed = |outcome| {
+            metrics::counter(
+                "app_tasks_completed_total",
+                "Demo tasks joined, by outcome.",
+                &[("outcome", outcome)],
+            )
+        };
+        tasks_completed("success").increment(report.results.len() as u64);
+        tasks_completed("failure").increment(report.errors.len() as u64);
+        tasks_completed("cancelled").increment(report.cancelled as u64);
+
+        if self.shutdown.is_requested() {
+            return Err(CoreError::Interrupted(format!(
+                "Shut down after {} of {task_count} demo tasks finished.",
+                report.results.len()
+            ))
+            .into());
         }
 
-        Ok(())
+        let rows: Vec<_> = report
+            .into_result()?
+            .into_iter()
+            .map(|(task, msg)| {
+                info!("Received msg \"{}\"", msg);
+                record(json!({ "task": task, "message": msg }))
+            })
+            .collect();
+
+        let text = rows
+            .iter()
+            .map(|row| row["message"].as_str().unwrap_or_default().to_string())
+            .collect();
+
+        Ok(CommandOutput::table(rows).with_text(text))
+    }
+}
+
+/// The redaction rules logs are masked by, for masking secrets in command output too.
+pub fn redactor_from_config(redaction: &app_config::Redaction) -> CoreResult<Redactor> {
+    Redactor::new(
+        redaction.enabled,
+        &redaction.replacement,
+        &redaction.fields,
+        &redaction.patterns,
+    )
+}
+
+fn config_get(key: &str) -> CoreResult<CommandOutput> {
+    let value: serde_json::Value = AppConfigManager::get(key)?;
+    let text = match &value {
+        serde_json::Value::String(value) => value.clone(),
+        value => serde_json::to_string_pretty(value)
+            .map_err(|e| CoreError::OutputError(e.to_string()))?,
+    };
+
+    Ok(CommandOutput::record(record(json!({ "key": key, "value": value }))).with_text(vec![text]))
+}
+
+fn config_sources() -> CommandOutput {
+    let sources = AppConfigManager::sources();
+
+    let rows = sources
+        .iter()
+        .enumerate()
+        .map(|(i, source)| record(json!({ "precedence": i + 1, "source": source.to_string() })))
+        .collect();
+    let text = sources
+        .iter()
+        .enumerate()
+        .map(|(i, source)| format!("{}. {source}", i + 1))
+        .collect();
+
+    CommandOutput::table(rows).with_text(text)
+}
+
+#[cfg(test)]
+mod app_runtime_tests {
+    use std::rc::Weak;
+
+    use cli::AppCommand;
+    use configuration::app_config::AppConfig;
+    use utils::{
+        assert_logged,
+        logging::testing::{capture_logs, Level},
+    };
+
+    use crate::{shutdown::Shutdown, AppRuntime};
+
+    #[tokio::test]
+    async fn test_tasks_logs_once_per_task() {
+        let logs = capture_logs(Level::TRACE);
+        let runtime = AppRuntime::new(Weak::new(), AppConfig::default());
+
+        let output = runtime.test_tasks(8, None).await.unwrap();
+
+        assert_eq!(8, output.value().as_array().unwrap().len());
+        assert_logged!(logs, level = INFO, message contains "Received msg", times = 8);
+        assert_logged!(logs, level = DEBUG, message contains "Entered task", times = 8);
+    }
+
+    #[tokio::test]
+    async fn test_tasks_stops_on_shutdown() {
+        let logs = capture_logs(Level::TRACE);
+        let shutdown = Shutdown::default();
+        let runtime =
+            AppRuntime::new(Weak::new(), AppConfig::default()).with_shutdown(shutdown.clone());
+
+        shutdown.request();
+        let result = runtime.test_tasks(8, None).await;
+
+        assert!(result.is_err());
+        assert_logged!(logs, level = INFO, message contains "Received msg", times = 0);
+    }
+
+    #[tokio::test]
+    async fn failed_command_logs_error() {
+        let logs = capture_logs(Level::TRACE);
+        let runtime = AppRuntime::new(Weak::new(), AppConfig::default());
+        // The registry is global, so other tests may have counted failures already.
+        let failures = metrics::counter(
+            "app_commands_total",
+            "",
+            &[("command", "file-error"), ("outcome", "failure")],
+        );
+        let before = failures.get();
+
+        let result = runtime.enter(AppCommand::FileError).await;
+
+        assert!(result.is_err());
+        assert_logged!(logs, level = ERROR, message contains "\"FileError\" failed", times = 1);
+        assert_eq!(1, failures.get() - before);
     }
 }
@@ -0,0 +1,121 @@
+use cli::{LogStyleArg, LoggingArgs};
+use configuration::app_config::{self, AppConfig};
+use tracing::Level;
+#[cfg(feature = "logfile")]
+use utils::logging::file_naming::LogFileNaming;
+use utils::{
+    core_types::CoreResult,
+    logging::{
+        format::{LogFormat, LogStyle},
+        rate_limit::RateLimitConfig,
+        ConsoleWriter, LoggingManager,
+    },
+};
+
+use crate::redactor_from_config;
+
+/// What the command line said about logging, kept so the config can be applied again when
+/// it's reloaded.
+#[derive(Debug, Clone, Default)]
+pub struct CommandLineLogging {
+    pub args: LoggingArgs,
+    /// The command, naming the log file when logging per invocation.
+    pub command: String,
+}
+
+fn log_format_from_config(format: &app_config::LogFormat) -> CoreResult<LogFormat> {
+    Ok(LogFormat {
+        style: format.style.parse()?,
+        timestamp: format.timestamp.parse()?,
+        target: format.target,
+        file_line: format.file_line,
+        thread_ids: format.thread_ids,
+        thread_names: format.thread_names,
+        span_close_events: format.span_close_events,
+        color: format.color.parse()?,
+    })
+}
+
+/// The console format from config, with `--log-format` taking precedence over its style.
+fn console_format(config: &AppConfig, args: &LoggingArgs) -> CoreResult<LogFormat> {
+    let mut format = log_format_from_config(&config.logging.cli_format)?;
+
+    if let Some(style) = args.log_format {
+        format.style = match style {
+            LogStyleArg::Full => LogStyle::Full,
+            LogStyleArg::Compact => LogStyle::Compact,
+            LogStyleArg::Pretty => LogStyle::Pretty,
+            LogStyleArg::Json => LogStyle::Json,
+        };
+    }
+
+    Ok(format)
+}
+
+/// Configure `log_manager` from `config`, with the command line taking precedence for the
+/// console. Takes effect on the next `refresh`, or through `LoggingManager::reconfigure`.
+pub fn configure(
+    log_manager: LoggingManager, config: &AppConfig, command_line: &CommandLineLogging,
+) -> CoreResult<LoggingManager> {
+    let redaction = &config.logging.redaction;
+    let rate_limit = &config.logging.rate_limit;
+    let sampling = &config.logging.sampling;
+    let args = &command_line.args;
+
+    let log_manager = log_manager
+        .with_redaction(redactor_from_config(redaction)?)
+        .with_rate_limit(RateLimitConfig::new(
+            rate_limit.enabled,
+            rate_limit.max_events,
+            rate_limit.interval_ms,
+            &rate_limit.key,
+            [
+                sampling.trace,
+                sampling.debug,
+                sampling.info,
+                sampling.warn,
+                sampling.error,
+            ],
+        )?)
+        .with_ring_buffer_logging(config.logging.ring_buffer_log_level.parse::<Level>()?)
+        .with_ring_buffer_format(log_format_from_config(&config.logging.ring_buffer_format)?)
+        .with_ring_buffer_capacity(config.logging.ring_buffer_capacity)
+        .with_ring_buffer_dump_path(config.logging.ring_buffer_dump_path.clone());
+
+    let console_level = args.console_level(config.logging.cli_log_level.parse::<Level>()?);
+
+    let log_manager = match console_level {
+        Some(level) if !args.quiet => log_manager
+            .with_fmt_logging(level)
+            .with_fmt_directives(args.log_level.clone().unwrap_or_default())
+            .with_fmt_format(console_format(config, args)?)
+            .with_fmt_writer(config.logging.cli_log_writer.parse::<ConsoleWriter>()?),
+        _ => log_manager.without_fmt_logging(),
+    };
+
+    #[cfg(feature = "journald")]
+    let log_manager = if config.logging.journald_enabled {
+        log_manager.with_journald_logging(config.logging.journald_log_level.parse::<Level>()?)
+    } else {
+        log_manager.without_journald_logging()
+    };
+
+    #[cfg(feature = "logfile")]
+    let log_manager = if config.logging.rolling_log_enabled {
+        log_manager
+            .with_logfile_logging(config.logging.rolling_log_level.parse::<Level>()?)
+            .with_logfile_prefix(config.logging.rolling_log_prefix.clone())
+            .with_logfile_base_path(config.logging.rolling_log_path.clone())
+            .with_logfile_format(log_format_from_config(&config.logging.rolling_log_format)?)
+            .with_logfile_max_files(config.logging.rolling_log_max_files)
+            .with_logfile_naming(if config.logging.rolling_log_per_invocation {
+                LogFileNaming::per_invocation(&command_line.command)
+            } else {
+                LogFileNaming::Daily
+            })
+    } else {
+        log_manager.without_logfile_logging()
+    };
+
+    Ok(log_manager)
+}
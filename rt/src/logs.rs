@@ -0,0 +1,570 @@
+use std::{
+    io::{Read, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use cli::{LogsSubCommand, OutputFormat};
+use flate2::read::GzDecoder;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, Level};
+use utils::{
+    core_types::{CoreError, CoreResult},
+    logging::file_naming::normalize_prefix,
+};
+
+use crate::output::{record, write_streamed, CommandOutput, Record};
+
+const COMPRESSED_SUFFIX: &str = ".gz";
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+lazy_static! {
+    static ref ANSI_ESCAPE: Regex = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+}
+
+/// A file written by the rolling file appender, or a compressed copy of one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub name: String,
+    /// The rotation period in the file name, such as `2024-01-31` or `2024-01-31-13`.
+    /// Empty for files that never rotate.
+    pub period: String,
+    pub compressed: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// The log files sharing a directory and file name prefix.
+#[derive(Debug)]
+pub struct LogDirectory {
+    dir: PathBuf,
+    prefix: String,
+}
+
+impl LogDirectory {
+    pub fn new(dir: impl Into<PathBuf>, prefix: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    /// Every log file in the directory, oldest first.
+    pub async fn files(&self) -> CoreResult<Vec<LogFile>> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(CoreError::from(e).into()),
+        };
+
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(CoreError::from)? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some((period, compressed)) = parse_rotation(&name, &self.prefix) else {
+                continue;
+            };
+
+            let metadata = entry.metadata().await.map_err(CoreError::from)?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            files.push(LogFile {
+                path: entry.path(),
+                name,
+                period,
+                compressed,
+                size: metadata.len(),
+                modified: metadata.modified().map_err(CoreError::from)?,
+            });
+        }
+
+        files.sort_by(|a, b| (&a.period, a.modified).cmp(&(&b.period, b.modified)));
+
+        Ok(files)
+    }
+
+    /// Find a file by path, falling back to a name within the log directory.
+    pub fn resolve(&self, file: &Path) -> PathBuf {
+        if file.is_absolute() || file.exists() {
+            file.to_path_buf()
+        } else {
+            self.dir.join(file)
+        }
+    }
+}
+
+/// Split a file name into its rotation period and whether it is compressed, if it was
+/// written by a `RollingFileAppender` with the given prefix and a `log` suffix.
+///
+/// Daily files are named `<prefix>.<YYYY-MM-DD>.log`, and per-invocation files
+/// `<prefix>.<YYYY-MM-DDTHH-MM-SSZ>.<command>.<pid>.log`. Either way the period starts
+/// with a date, so sorting by period sorts by age. Files that never rotate have no period.
+/// Files from before prefixes were normalised, named `<prefix>.log.<YYYY-MM-DD>.log`, are
+/// recognised too.
+fn parse_rotation(name: &str, prefix: &str) -> Option<(String, bool)> {
+    let (name, compressed) = match name.strip_suffix(COMPRESSED_SUFFIX) {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+
+    let prefix = normalize_prefix(prefix);
+    let legacy_prefix = format!("{prefix}.log");
+
+    let period = [legacy_prefix.as_str(), prefix]
+        .into_iter()
+        .filter_map(|prefix| name.strip_prefix(prefix))
+        .map(|rest| rest.strip_suffix(".log").unwrap_or(rest))
+        .map(|rest| rest.strip_prefix('.').unwrap_or(rest))
+        .find(|period| is_period(period))
+        .map(|period| (period.to_string(), compressed));
+
+    period
+}
+
+fn is_period(period: &str) -> bool {
+    let date = period.split('.').next().unwrap_or_default();
+
+    period.is_empty()
+        || (date.len() >= 10
+            && NaiveDate::parse_from_str(&date[..10], "%Y-%m-%d").is_ok()
+            && date[10..]
+                .chars()
+                .all(|c| c.is_ascii_digit() || matches!(c, '-' | 'T' | 'Z')))
+}
+
+async fn read_lines(path: &Path) -> CoreResult<Vec<String>> {
+    let bytes = tokio::fs::read(path).await.map_err(CoreError::from)?;
+
+    let text = if path.to_string_lossy().ends_with(COMPRESSED_SUFFIX) {
+        let mut text = String::new();
+        GzDecoder::new(bytes.as_slice())
+            .read_to_string(&mut text)
+            .map_err(CoreError::from)?;
+        text
+    } else {
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    Ok(text.lines().map(str::to_string).collect())
+}
+
+/// A log line with the parts needed to filter it.
+#[derive(Debug)]
+struct LogLine {
+    timestamp: Option<DateTime<Utc>>,
+    level: Option<Level>,
+    rendered: String,
+}
+
+impl LogLine {
+    fn parse(line: &str) -> Self {
+        if let Ok(Value::Object(object)) = serde_json::from_str::<Value>(line) {
+            return Self::from_json(&object);
+        }
+
+        let line = ANSI_ESCAPE.replace_all(line, "");
+        let mut tokens = line.split_whitespace();
+
+        Self {
+            timestamp: tokens.clone().next().and_then(parse_timestamp),
+            level: tokens.by_ref().take(3).find_map(parse_level),
+            rendered: line.into_owned(),
+        }
+    }
+
+    /// Lay out a line written by the JSON formatter like the text formatter would.
+    fn from_json(object: &Map<String, Value>) -> Self {
+        let text = |key: &str| object.get(key).and_then(Value::as_str).unwrap_or_default();
+
+        let mut parts = Vec::new();
+        if !text("timestamp").is_empty() {
+            parts.push(text("timestamp").to_string());
+        }
+        parts.push(format!("{:>5}", text("level")));
+
+        let spans = object
+            .get("spans")
+            .and_then(Value::as_array)
+            .map(|spans| {
+                spans
+                    .iter()
+                    .filter_map(|span| span.get("name").and_then(Value::as_str))
+                    .collect::<Vec<&str>>()
+                    .join(":")
+            })
+            .unwrap_or_default();
+        if !spans.is_empty() {
+            parts.push(format!("{spans}:"));
+        }
+        if !text("target").is_empty() {
+            parts.push(format!("{}:", text("target")));
+        }
+
+        if let Some(Value::Object(fields)) = object.get("fields") {
+            if let Some(message) = fields.get("message") {
+                parts.push(json_text(message));
+            }
+            for (key, value) in fields.iter().filter(|(key, _)| *key != "message") {
+                parts.push(format!("{key}={}", json_text(value)));
+            }
+        }
+
+        Self {
+            timestamp: parse_timestamp(text("timestamp")),
+            level: parse_level(text("level")),
+            rendered: parts.join(" "),
+        }
+    }
+
+    fn to_record(&self) -> Record {
+        record(json!({
+            "timestamp": self.timestamp.map(|timestamp| timestamp.to_rfc3339()),
+            "level": self.level.map(|level| level.to_string()),
+            "line": self.rendered,
+        }))
+    }
+
+    fn write_streamed(&self, format: OutputFormat) -> CoreResult<()> {
+        write_streamed(
+            format,
+            &self.to_record(),
+            &self.rendered,
+            &mut std::io::stdout(),
+        )
+    }
+}
+
+fn json_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn parse_timestamp(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+fn parse_level(text: &str) -> Option<Level> {
+    // `Level` also parses numbers, which are too easily mistaken for other fields.
+    match text {
+        "TRACE" | "DEBUG" | "INFO" | "WARN" | "ERROR" => text.parse().ok(),
+        _ => None,
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// Run a logs command. Following a log stops once `shutdown` is cancelled.
+pub async fn run(
+    directory: &LogDirectory, command: LogsSubCommand, format: OutputFormat,
+    shutdown: CancellationToken,
+) -> CoreResult<CommandOutput> {
+    match command {
+        LogsSubCommand::List => list(directory).await,
+        LogsSubCommand::Tail { lines, follow } => {
+            tail(directory, lines, follow, format, shutdown).await
+        }
+        LogsSubCommand::Grep {
+            pattern,
+            level,
+            since,
+        } => grep(directory, &pattern, level, since).await,
+        LogsSubCommand::Show { file } => show(directory, &file).await,
+    }
+}
+
+async fn list(directory: &LogDirectory) -> CoreResult<CommandOutput> {
+    let files = directory.files().await?;
+
+    let text = if files.is_empty() {
+        vec![format!(
+            "No log files found in \"{}\".",
+            directory.dir.display()
+        )]
+    } else {
+        files
+            .iter()
+            .map(|file| {
+                format!(
+                    "{:<48} {:>10}  {}{}",
+                    file.name,
+                    format_size(file.size),
+                    DateTime::<Local>::from(file.modified).format("%Y-%m-%d %H:%M:%S"),
+                    if file.compressed {
+                        "  (compressed)"
+                    } else {
+                        ""
+                    }
+                )
+            })
+            .collect()
+    };
+
+    let rows = files
+        .iter()
+        .map(|file| {
+            record(json!({
+                "name": file.name,
+                "size": file.size,
+                "modified": DateTime::<Local>::from(file.modified).to_rfc3339(),
+                "compressed": file.compressed,
+            }))
+        })
+        .collect();
+
+    Ok(CommandOutput::table(rows).with_text(text))
+}
+
+/// Log lines as output, with the parsed timestamp and level alongside each line.
+fn lines_output(lines: Vec<LogLine>) -> CommandOutput {
+    let text = lines.iter().map(|line| line.rendered.clone()).collect();
+
+    CommandOutput::table(lines.iter().map(LogLine::to_record).collect()).with_text(text)
+}
+
+async fn tail(
+    directory: &LogDirectory, count: usize, follow: bool, format: OutputFormat,
+    shutdown: CancellationToken,
+) -> CoreResult<CommandOutput> {
+    let files = directory.files().await?;
+
+    // Walk back through older files until enough lines are found.
+    let mut lines = Vec::new();
+    for file in files.iter().rev() {
+        if lines.len() >= count {
+            break;
+        }
+        let mut older = read_lines(&file.path).await?;
+        older.append(&mut lines);
+        lines = older;
+    }
+
+    let lines: Vec<LogLine> = lines[lines.len().saturating_sub(count)..]
+        .iter()
+        .map(|line| LogLine::parse(line))
+        .collect();
+
+    if !follow {
+        return Ok(lines_output(lines));
+    }
+
+    // Following never finishes, so lines are streamed as they come rather than collected.
+    for line in &lines {
+        line.write_streamed(format)?;
+    }
+    follow_files(
+        directory,
+        files.last().map(|file| file.path.clone()),
+        format,
+        shutdown,
+    )
+    .await?;
+
+    Ok(CommandOutput::written())
+}
+
+/// Print lines as they are appended to the newest log file, moving on to each new file as
+/// the appender rotates. Runs until `shutdown` is cancelled.
+async fn follow_files(
+    directory: &LogDirectory, mut current: Option<PathBuf>, format: OutputFormat,
+    shutdown: CancellationToken,
+) -> CoreResult<()> {
+    let mut position = match &current {
+        Some(path) => tokio::fs::metadata(path)
+            .await
+            .map_err(CoreError::from)?
+            .len(),
+        None => 0,
+    };
+    let mut partial = String::new();
+
+    loop {
+        if let Some(path) = &current {
+            let length = match tokio::fs::metadata(path).await {
+                Ok(metadata) => metadata.len(),
+                Err(_) => 0,
+            };
+
+            if length < position {
+                debug!(
+                    "\"{}\" was truncated. Reading from the start.",
+                    path.display()
+                );
+                position = 0;
+            }
+
+            if length > position {
+                let mut file = tokio::fs::File::open(path).await.map_err(CoreError::from)?;
+                file.seek(SeekFrom::Start(position))
+                    .await
+                    .map_err(CoreError::from)?;
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes)
+                    .await
+                    .map_err(CoreError::from)?;
+                position += bytes.len() as u64;
+
+                partial.push_str(&String::from_utf8_lossy(&bytes));
+                while let Some(end) = partial.find('\n') {
+                    let line: String = partial.drain(..=end).collect();
+                    LogLine::parse(line.trim_end()).write_streamed(format)?;
+                }
+            }
+        }
+
+        let newest = directory
+            .files()
+            .await?
+            .into_iter()
+            .rfind(|file| !file.compressed)
+            .map(|file| file.path);
+
+        if let Some(newest) = newest.filter(|newest| Some(newest) != current.as_ref()) {
+            debug!("Following \"{}\".", newest.display());
+            current = Some(newest);
+            position = 0;
+            partial.clear();
+            continue;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(FOLLOW_POLL_INTERVAL) => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+    }
+}
+
+async fn grep(
+    directory: &LogDirectory, pattern: &str, level: Option<Level>, since: Option<Duration>,
+) -> CoreResult<CommandOutput> {
+    let pattern = Regex::new(pattern)?;
+    let cutoff = since.map(|since| SystemTime::now() - since);
+    let mut matches = Vec::new();
+
+    for file in directory.files().await? {
+        // A file last written before the cutoff can't hold anything newer.
+        if cutoff.is_some_and(|cutoff| file.modified < cutoff) {
+            continue;
+        }
+
+        for line in read_lines(&file.path).await? {
+            let line = LogLine::parse(&line);
+
+            // Lines without a level, such as the rest of a multi-line message, are only
+            // shown when not filtering by level.
+            if level.is_some_and(|level| line.level.is_none_or(|found| found > level)) {
+                continue;
+            }
+
+            if let (Some(cutoff), Some(timestamp)) = (cutoff, line.timestamp) {
+                if timestamp < DateTime::<Utc>::from(cutoff) {
+                    continue;
+                }
+            }
+
+            if pattern.is_match(&line.rendered) {
+                matches.push(line);
+            }
+        }
+    }
+
+    Ok(lines_output(matches))
+}
+
+async fn show(directory: &LogDirectory, file: &Path) -> CoreResult<CommandOutput> {
+    let lines = read_lines(&directory.resolve(file)).await?;
+
+    Ok(lines_output(
+        lines.iter().map(|line| LogLine::parse(line)).collect(),
+    ))
+}
+
+#[cfg(test)]
+mod logs_tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use tracing::Level;
+
+    use super::{parse_rotation, read_lines, LogDirectory, LogLine};
+
+    #[test]
+    fn rotation_names_are_recognised() {
+        assert_eq!(
+            Some(("2024-01-31".to_string(), false)),
+            parse_rotation("app.2024-01-31.log", "app")
+        );
+        assert_eq!(
+            Some(("2024-01-31-13".to_string(), true)),
+            parse_rotation("app.2024-01-31-13.log.gz", "app")
+        );
+        assert_eq!(
+            Some((String::new(), false)),
+            parse_rotation("app.log", "app")
+        );
+        assert_eq!(
+            Some(("2024-01-31".to_string(), false)),
+            parse_rotation("app.log.2024-01-31.log", "app")
+        );
+        assert_eq!(None, parse_rotation("app.backup.log", "app"));
+        assert_eq!(None, parse_rotation("other.2024-01-31.log", "app"));
+    }
+
+    #[test]
+    fn json_lines_are_rendered_as_text() {
+        let line = LogLine::parse(
+            r#"{"timestamp":"2024-01-31T10:00:00.000000Z","level":"WARN","fields":{"message":"Disk low","free":12},"target":"rt","spans":[{"name":"enter"},{"name":"check"}]}"#,
+        );
+
+        assert_eq!(Some(Level::WARN), line.level);
+        assert!(line.timestamp.is_some());
+        assert_eq!(
+            "2024-01-31T10:00:00.000000Z  WARN enter:check: rt: Disk low free=12",
+            line.rendered
+        );
+    }
+
+    #[tokio::test]
+    async fn compressed_files_are_listed_and_read() {
+        let dir = std::env::temp_dir().join(format!("logs-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        std::fs::write(dir.join("app.2024-01-31.log"), "newer\n").unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"older\n").unwrap();
+        std::fs::write(dir.join("app.2024-01-30.log.gz"), encoder.finish().unwrap()).unwrap();
+
+        let files = LogDirectory::new(&dir, "app").files().await.unwrap();
+        let first = read_lines(&files[0].path).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(2, files.len());
+        assert!(files[0].compressed);
+        assert_eq!(vec!["older".to_string()], first);
+    }
+}
@@ -0,0 +1,68 @@
+use cli::plugins;
+use serde_json::Value;
+use tracing::{debug, info};
+use utils::{
+    core_types::{CoreError, CoreResult},
+    project_name_str,
+};
+
+use crate::output::CommandOutput;
+
+/// The resolved config, as JSON, is passed to plugins in this variable.
+pub const CONFIG_VAR: &str = "RUST_STARTER_TEMPLATE_PLUGIN_CONFIG";
+/// The console log level, such as `info` or `off`, is passed to plugins in this variable.
+pub const LOG_LEVEL_VAR: &str = "RUST_STARTER_TEMPLATE_PLUGIN_LOG_LEVEL";
+
+/// Run the plugin for `args[0]` with the rest of `args` and the resolved `config`,
+/// sharing this process's stdio.
+pub async fn run(args: Vec<String>, config: &Value, log_level: &str) -> CoreResult<CommandOutput> {
+    let Some((name, args)) = args.split_first() else {
+        return Err(CoreError::GeneralTaskError("No plugin was named.".to_string()).into());
+    };
+
+    if !plugins::is_valid_name(name) {
+        return Err(CoreError::GeneralTaskError(format!(
+            "\"{name}\" isn't a command, and can't name a plugin as it contains a path \
+             separator."
+        ))
+        .into());
+    }
+
+    let plugin_dir = cli::plugin_dir();
+    let plugin = plugins::find(name, plugin_dir.as_deref()).ok_or_else(|| {
+        let searched = plugin_dir
+            .map(|dir| format!("\"{}\" or PATH", dir.display()))
+            .unwrap_or_else(|| "PATH".to_string());
+
+        let suggestion = cli::similar_command(name)
+            .map(|similar| format!(" Did you mean \"{similar}\"?"))
+            .unwrap_or_default();
+
+        CoreError::GeneralTaskError(format!(
+            "Unknown command \"{name}\", and no plugin named \"{}-{name}\" was found in \
+             {searched}.{suggestion}",
+            project_name_str!()
+        ))
+    })?;
+
+    debug!("Running plugin \"{}\".", plugin.path.display());
+    let status = tokio::process::Command::new(&plugin.path)
+        .args(args)
+        .env(CONFIG_VAR, config.to_string())
+        .env(LOG_LEVEL_VAR, log_level)
+        .status()
+        .await
+        .map_err(CoreError::from)?;
+
+    if !status.success() {
+        return Err(CoreError::GeneralTaskError(format!(
+            "Plugin \"{name}\" failed with {status}."
+        ))
+        .into());
+    }
+
+    info!("Plugin \"{name}\" finished.");
+
+    // The plugin writes its own output.
+    Ok(CommandOutput::written())
+}
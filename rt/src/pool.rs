@@ -0,0 +1,575 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use configuration::app_config;
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::JoinSet,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use utils::{
+    core_types::{CoreError, CoreResult},
+    random::Xorshift,
+};
+
+use crate::{backoff::Backoff, tasks::TaskRegistry};
+
+/// How many failed items are described in an aggregated error before the rest are counted.
+const MAX_REPORTED_ERRORS: usize = 5;
+
+/// How far a pool has got, passed to its progress callback after each item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub succeeded: usize,
+    pub failed: usize,
+    /// The number of items, when the source knows it up front.
+    pub total: Option<usize>,
+}
+
+impl Progress {
+    pub fn done(&self) -> usize {
+        self.succeeded + self.failed
+    }
+}
+
+/// An item that failed on every attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemError {
+    /// The item's position in the source.
+    pub index: usize,
+    pub attempts: u32,
+    pub message: String,
+}
+
+/// What came of a pool run. Results are in source order if the pool is ordered, and in the
+/// order they finished otherwise.
+#[derive(Debug)]
+pub struct PoolReport<R> {
+    pub results: Vec<(usize, R)>,
+    /// Failed items, in source order.
+    pub errors: Vec<ItemError>,
+    /// Items queued but never worked on, as shutdown was requested.
+    pub cancelled: usize,
+}
+
+impl<R> PoolReport<R> {
+    /// The results alone, or one error describing every failed or cancelled item.
+    pub fn into_result(self) -> CoreResult<Vec<R>> {
+        if self.errors.is_empty() && self.cancelled == 0 {
+            return Ok(self.results.into_iter().map(|(_, result)| result).collect());
+        }
+
+        let total = self.results.len() + self.errors.len() + self.cancelled;
+        let mut message = format!("{} of {total} items failed", self.errors.len());
+        if self.cancelled > 0 {
+            message.push_str(&format!(" and {} were cancelled", self.cancelled));
+        }
+
+        let described: Vec<String> = self
+            .errors
+            .iter()
+            .take(MAX_REPORTED_ERRORS)
+            .map(|error| {
+                format!(
+                    "item {} after {} attempts: {}",
+                    error.index, error.attempts, error.message
+                )
+            })
+            .collect();
+        if !described.is_empty() {
+            message.push_str(&format!(". {}", described.join("; ")));
+        }
+        if self.errors.len() > MAX_REPORTED_ERRORS {
+            message.push_str(&format!(
+                "; and {} more",
+                self.errors.len() - MAX_REPORTED_ERRORS
+            ));
+        }
+        message.push('.');
+
+        Err(CoreError::WorkerPoolError(message).into())
+    }
+}
+
+/// Works through items with at most `concurrency` at once. Items are taken from their
+/// source through a bounded queue, so a source faster than the workers waits rather than
+/// piling up in memory.
+#[derive(Clone)]
+pub struct WorkerPool {
+    concurrency: usize,
+    queue_capacity: usize,
+    ordered: bool,
+    timeout: Option<Duration>,
+    retries: u32,
+    backoff: Backoff,
+    shutdown: CancellationToken,
+    tracking: Option<(Arc<TaskRegistry>, String)>,
+    progress: Option<Arc<dyn Fn(Progress) + Send + Sync>>,
+}
+
+impl std::fmt::Debug for WorkerPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("concurrency", &self.concurrency)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("ordered", &self.ordered)
+            .field("timeout", &self.timeout)
+            .field("retries", &self.retries)
+            .field("backoff", &self.backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WorkerPool {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            queue_capacity: concurrency.max(1),
+            ordered: false,
+            timeout: None,
+            retries: 0,
+            backoff: Backoff {
+                initial: Duration::from_millis(100),
+                max: Duration::from_secs(5),
+                jitter: 0.2,
+            },
+            shutdown: CancellationToken::new(),
+            tracking: None,
+            progress: None,
+        }
+    }
+
+    /// A pool set up from config, with one worker per CPU unless a concurrency is set.
+    pub fn from_config(config: &app_config::Pool) -> Self {
+        let concurrency = match config.concurrency {
+            0 => std::thread::available_parallelism().map_or(1, usize::from),
+            concurrency => concurrency,
+        };
+
+        Self::new(concurrency)
+            .with_queue_capacity(config.queue_capacity)
+            .with_timeout((config.timeout_ms > 0).then(|| Duration::from_millis(config.timeout_ms)))
+            .with_retries(
+                config.retries,
+                Backoff {
+                    initial: Duration::from_millis(config.retry_backoff_initial_ms),
+                    max: Duration::from_millis(config.retry_backoff_max_ms),
+                    jitter: 0.2,
+                },
+            )
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// Return results in source order, rather than as they finish.
+    pub fn with_ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Give up on each attempt at an item after `timeout`, if set.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Retry failed or timed out items up to `retries` times, waiting by `backoff` first.
+    pub fn with_retries(mut self, retries: u32, backoff: Backoff) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Stop taking new items once `token` is cancelled. Items already started are finished,
+    /// but not retried.
+    pub fn with_shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// List each attempt in `tasks`, named `<name> <index>`.
+    pub fn with_tracking(mut self, tasks: Arc<TaskRegistry>, name: impl Into<String>) -> Self {
+        self.tracking = Some((tasks, name.into()));
+        self
+    }
+
+    /// Call `progress` after each item finishes.
+    pub fn with_progress(mut self, progress: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Run `work` on every item from `items`, returning once all are done, or once those
+    /// started are done after shutdown is requested.
+    pub async fn run<I, F, Fut, R>(&self, items: I, work: F) -> PoolReport<R>
+    where
+        I: IntoIterator,
+        I::Item: Clone + Send + 'static,
+        F: Fn(I::Item) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CoreResult<R>> + Send + 'static,
+        R: Send + 'static,
+    {
+        let items = items.into_iter();
+        let total = match items.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        };
+
+        let work = Arc::new(work);
+        let (item_sender, item_receiver) = mpsc::channel(self.queue_capacity);
+        let item_receiver = Arc::new(Mutex::new(item_receiver));
+        let (result_sender, mut result_receiver) = mpsc::unbounded_channel();
+
+        let mut workers = JoinSet::new();
+        for _ in 0..self.concurrency {
+            let worker = Worker {
+                pool: self.clone(),
+                work: work.clone(),
+                items: item_receiver.clone(),
+                results: result_sender.clone(),
+            };
+            workers.spawn(worker.run());
+        }
+        // The results end once every worker has stopped.
+        drop(result_sender);
+
+        let produce = async {
+            let mut queued = 0;
+            for item in items.enumerate() {
+                tokio::select! {
+                    biased;
+                    _ = self.shutdown.cancelled() => break,
+                    // Waits while the queue is full.
+                    sent = item_sender.send(item) => {
+                        if sent.is_err() {
+                            break;
+                        }
+                        queued += 1;
+                    }
+                }
+            }
+            drop(item_sender);
+
+            queued
+        };
+
+        let collect = async {
+            let mut report = PoolReport {
+                results: Vec::new(),
+                errors: Vec::new(),
+                cancelled: 0,
+            };
+
+            while let Some((index, outcome)) = result_receiver.recv().await {
+                let outcome_label = match outcome {
+                    Ok(result) => {
+                        report.results.push((index, result));
+                        "success"
+                    }
+                    Err(error) => {
+                        report.errors.push(error);
+                        "failure"
+                    }
+                };
+                metrics::counter(
+                    "app_pool_items_total",
+                    "Items worked on by worker pools, by outcome.",
+                    &[("outcome", outcome_label)],
+                )
+                .increment(1);
+
+                if let Some(progress) = &self.progress {
+                    progress(Progress {
+                        succeeded: report.results.len(),
+                        failed: report.errors.len(),
+                        total,
+                    });
+                }
+            }
+
+            report
+        };
+
+        let (queued, mut report) = tokio::join!(produce, collect);
+        report.cancelled = queued - report.results.len() - report.errors.len();
+
+        if self.ordered {
+            report.results.sort_by_key(|(index, _)| *index);
+        }
+        report.errors.sort_by_key(|error| error.index);
+
+        report
+    }
+}
+
+type ItemOutcome<R> = (usize, Result<R, ItemError>);
+
+struct Worker<T, F, R> {
+    pool: WorkerPool,
+    work: Arc<F>,
+    items: Arc<Mutex<mpsc::Receiver<(usize, T)>>>,
+    results: mpsc::UnboundedSender<ItemOutcome<R>>,
+}
+
+impl<T, F, Fut, R> Worker<T, F, R>
+where
+    T: Clone + Send + 'static,
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = CoreResult<R>> + Send + 'static,
+    R: Send + 'static,
+{
+    async fn run(self) {
+        let random = Xorshift::new();
+
+        loop {
+            let next = {
+                let mut items = self.items.lock().await;
+                tokio::select! {
+                    biased;
+                    _ = self.pool.shutdown.cancelled() => None,
+                    item = items.recv() => item,
+                }
+            };
+            let Some((index, item)) = next else {
+                return;
+            };
+
+            let outcome = self.attempt(index, item, &random).await;
+            if self.results.send((index, outcome)).is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn attempt(&self, index: usize, item: T, random: &Xorshift) -> Result<R, ItemError> {
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            let result = self.attempt_once(index, item.clone()).await;
+            match result {
+                Ok(result) => return Ok(result),
+                Err(message)
+                    if attempts <= self.pool.retries && !self.pool.shutdown.is_cancelled() =>
+                {
+                    let delay = self.pool.backoff.delay(attempts - 1, random.next_f64());
+                    debug!(
+                        "Item {index} failed on attempt {attempts}, retrying in {}ms: {message}",
+                        delay.as_millis()
+                    );
+                    metrics::counter(
+                        "app_pool_retries_total",
+                        "Items retried by worker pools.",
+                        &[],
+                    )
+                    .increment(1);
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        // Shutdown fails the item rather than waiting to retry it.
+                        _ = self.pool.shutdown.cancelled() => {
+                            return Err(ItemError {
+                                index,
+                                attempts,
+                                message,
+                            })
+                        }
+                    }
+                }
+                Err(message) => {
+                    return Err(ItemError {
+                        index,
+                        attempts,
+                        message,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Each attempt runs as its own task, so a panic fails the item rather than the worker,
+    /// and an attempt that times out can be aborted.
+    async fn attempt_once(&self, index: usize, item: T) -> Result<R, String> {
+        let future = (self.work)(item);
+        let mut handle = match &self.pool.tracking {
+            Some((tasks, name)) => tokio::spawn(tasks.track(format!("{name} {index}"), future)),
+            None => tokio::spawn(future),
+        };
+
+        let joined = match self.pool.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, &mut handle).await {
+                Ok(joined) => joined,
+                Err(_) => {
+                    handle.abort();
+                    return Err(format!("Timed out after {}ms.", timeout.as_millis()));
+                }
+            },
+            None => handle.await,
+        };
+
+        match joined {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod pool_tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use tokio_util::sync::CancellationToken;
+    use utils::core_types::CoreError;
+
+    use super::WorkerPool;
+    use crate::backoff::Backoff;
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrency_is_bounded_and_results_ordered() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+
+        let (counter, most) = (running.clone(), most_running.clone());
+        let report = WorkerPool::new(3)
+            .with_ordered(true)
+            .run(0..20u64, move |i| {
+                let (counter, most) = (counter.clone(), most.clone());
+                async move {
+                    most.fetch_max(counter.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    // Later items finish first, so only ordering puts them back in place.
+                    tokio::time::sleep(Duration::from_millis(100 - i)).await;
+                    counter.fetch_sub(1, Ordering::SeqCst);
+                    Ok(i * 2)
+                }
+            })
+            .await;
+
+        assert_eq!(3, most_running.load(Ordering::SeqCst));
+        assert_eq!(
+            (0..20).map(|i| i * 2).collect::<Vec<_>>(),
+            report.into_result().unwrap()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failures_are_retried_timed_out_and_aggregated() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let counter = attempts.clone();
+        let report = WorkerPool::new(2)
+            .with_timeout(Some(Duration::from_secs(1)))
+            .with_retries(
+                2,
+                Backoff {
+                    initial: Duration::from_millis(10),
+                    max: Duration::from_secs(1),
+                    jitter: 0.0,
+                },
+            )
+            .run(0..4usize, move |i| {
+                let counter = counter.clone();
+                async move {
+                    match i {
+                        // Fails twice, then succeeds on its last retry.
+                        0 if counter.fetch_add(1, Ordering::SeqCst) < 2 => {
+                            Err(CoreError::GeneralTaskError("Flaky".to_string()).into())
+                        }
+                        1 => Err(CoreError::GeneralTaskError("Broken".to_string()).into()),
+                        2 => {
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                            Ok(i)
+                        }
+                        _ => Ok(i),
+                    }
+                }
+            })
+            .await;
+
+        assert_eq!(2, report.results.len());
+        assert_eq!(
+            vec![1, 2],
+            report.errors.iter().map(|e| e.index).collect::<Vec<_>>()
+        );
+        assert!(report.errors.iter().all(|error| error.attempts == 3));
+        assert_eq!("Timed out after 1000ms.", report.errors[1].message);
+
+        let error = report.into_result().unwrap_err().to_string();
+        assert!(error.starts_with("Worker pool Error: 2 of 4 items failed."));
+        assert!(error.contains("item 1 after 3 attempts: General Task Error: Broken"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_cancels_queued_items() {
+        let shutdown = CancellationToken::new();
+
+        let token = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            token.cancel();
+        });
+
+        // The first item is still being worked on at shutdown, with three more queued.
+        let report = WorkerPool::new(1)
+            .with_queue_capacity(3)
+            .with_shutdown(shutdown)
+            .run(0..10usize, |i| async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok(i)
+            })
+            .await;
+
+        assert_eq!(vec![(0, 0)], report.results);
+        assert_eq!(3, report.cancelled);
+        assert!(report
+            .into_result()
+            .unwrap_err()
+            .to_string()
+            .contains("0 of 4 items failed and 3 were cancelled"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_stops_waiting_to_retry() {
+        let shutdown = CancellationToken::new();
+        let started = tokio::time::Instant::now();
+
+        let token = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            token.cancel();
+        });
+
+        let report = WorkerPool::new(1)
+            .with_shutdown(shutdown)
+            .with_retries(
+                5,
+                Backoff {
+                    initial: Duration::from_secs(3600),
+                    max: Duration::from_secs(3600),
+                    jitter: 0.0,
+                },
+            )
+            .run(0..1usize, |_| async {
+                Err::<(), _>(CoreError::GeneralTaskError("Broken".to_string()).into())
+            })
+            .await;
+
+        assert!(started.elapsed() < Duration::from_secs(3600));
+        assert_eq!(1, report.errors.len());
+        assert_eq!(1, report.errors[0].attempts);
+    }
+}
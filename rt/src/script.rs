@@ -0,0 +1,376 @@
+use std::{
+    io::Read,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use cli::{AppCommand, OutputFormat};
+use configuration::AppConfigManager;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde_json::{json, Value};
+use tracing::info;
+use utils::core_types::{CoreError, CoreResult};
+
+use crate::{
+    output::{record, CommandOutput},
+    shell::{parse_words, split_line, usage_error},
+    AppRuntime,
+};
+
+lazy_static! {
+    /// A config key to substitute, such as `${logging.rolling_log_path}`.
+    static ref VARIABLE: Regex = Regex::new(r"\$\{([^}]*)\}").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Ok,
+    Failed,
+    Skipped,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Failed => "failed",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+struct LineResult {
+    number: usize,
+    line: String,
+    status: Status,
+    duration: Option<Duration>,
+    error: Option<String>,
+    /// What the line's command produced, kept for JSON and YAML so the script writes a
+    /// single document.
+    output: Value,
+}
+
+/// Run each command in the script at `file` (`-` for stdin) in turn, and report how each
+/// went. Unless `continue_on_error` is set, the first failure skips the rest. Each
+/// command's output is written as it finishes, except in JSON and YAML, where it's
+/// included in the report instead.
+pub async fn run(
+    runtime: &AppRuntime, file: &Path, continue_on_error: bool, output_format: OutputFormat,
+) -> CoreResult<CommandOutput> {
+    let script = read_script(runtime, file).await?;
+    let results = run_lines(runtime, &script, continue_on_error, output_format).await;
+
+    let count = |status| {
+        results
+            .iter()
+            .filter(|result| result.status == status)
+            .count()
+    };
+    let failed = count(Status::Failed);
+    let summary = format!(
+        "{} commands: {} succeeded, {failed} failed, {} skipped.",
+        results.len(),
+        count(Status::Ok),
+        count(Status::Skipped)
+    );
+    info!("Script \"{}\" finished. {summary}", file.display());
+
+    let output = summary_output(&results, summary, output_format);
+    if failed > 0 {
+        // The summary matters most when something failed, so it's written before the error.
+        output.write(output_format, &mut std::io::stdout())?;
+
+        return Err(CoreError::GeneralTaskError(format!(
+            "{failed} of {} script commands failed.",
+            results.len()
+        ))
+        .into());
+    }
+
+    Ok(output)
+}
+
+/// Run the lines of `script`, skipping the rest after a failure unless `continue_on_error`,
+/// and after shutdown is requested.
+async fn run_lines(
+    runtime: &AppRuntime, script: &str, continue_on_error: bool, output_format: OutputFormat,
+) -> Vec<LineResult> {
+    let mut results = Vec::new();
+    let mut stopped = false;
+
+    for (index, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Shutdown skips the rest, as a failure would.
+        stopped |= runtime.shutdown.is_requested();
+
+        let mut result = LineResult {
+            number: index + 1,
+            line: line.to_string(),
+            status: Status::Skipped,
+            duration: None,
+            error: None,
+            output: Value::Null,
+        };
+
+        if !stopped {
+            let started = Instant::now();
+            let outcome = run_line(runtime, line, output_format).await;
+            result.duration = Some(started.elapsed());
+
+            match outcome {
+                Ok(output) => {
+                    result.status = Status::Ok;
+                    result.output = output;
+                }
+                Err(e) => {
+                    result.status = Status::Failed;
+                    result.error = Some(e.to_string());
+                    stopped = !continue_on_error;
+                }
+            }
+        }
+
+        results.push(result);
+    }
+
+    results
+}
+
+async fn read_script(runtime: &AppRuntime, file: &Path) -> CoreResult<String> {
+    if file != Path::new("-") {
+        return std::fs::read_to_string(file).map_err(|e| CoreError::from(e).into());
+    }
+
+    // Stdin may be a terminal that's never closed, so shutdown mustn't wait on it.
+    runtime
+        .shutdown
+        .run_blocking(|| {
+            let mut script = String::new();
+            std::io::stdin().read_to_string(&mut script).map(|_| script)
+        })
+        .await?
+        .map_err(|e| CoreError::from(e).into())
+}
+
+/// Run one line of a script. Its output is written straight away for people, or returned
+/// for JSON and YAML.
+async fn run_line(
+    runtime: &AppRuntime, line: &str, output_format: OutputFormat,
+) -> CoreResult<Value> {
+    let words = substitute(line)?;
+    let collect = matches!(output_format, OutputFormat::Json | OutputFormat::Yaml);
+
+    let (command, line_format) = match parse_words(words)? {
+        Ok(parsed) => parsed,
+        // Help asked for in a script is output like anything else.
+        Err(e) if !e.use_stderr() && collect => return Ok(Value::String(e.to_string())),
+        Err(e) if !e.use_stderr() => {
+            return e
+                .print()
+                .map(|()| Value::Null)
+                .map_err(|e| CoreError::from(e).into())
+        }
+        Err(e) => return Err(usage_error(&e).into()),
+    };
+
+    if let Some(name) = interactive_or_nested(&command) {
+        return Err(
+            CoreError::GeneralTaskError(format!("\"{name}\" can't be run from a script.")).into(),
+        );
+    }
+
+    let line_format = line_format.unwrap_or(output_format);
+    // Boxed, as the script is itself run from `enter`.
+    let output = Box::pin(runtime.execute(command, line_format)).await?;
+    if collect {
+        return Ok(output.value());
+    }
+
+    output.write(line_format, &mut std::io::stdout())?;
+    Ok(Value::Null)
+}
+
+/// The name of `command` if it can't run in a script: the shell and dashboard wait on the
+/// terminal, and a script running scripts could run itself forever.
+fn interactive_or_nested(command: &AppCommand) -> Option<&'static str> {
+    match command {
+        AppCommand::Shell | AppCommand::RunScript { .. } => Some(command.name()),
+        #[cfg(feature = "tui")]
+        AppCommand::Tui { .. } => Some(command.name()),
+        _ => None,
+    }
+}
+
+/// Split `line` into words, then replace each `${key}` in them with the value of the config
+/// key. Values are substituted after splitting, so spaces and quotes in them stay in their
+/// word. Strings are inserted as they are, and anything else as JSON.
+fn substitute(line: &str) -> CoreResult<Vec<String>> {
+    // Spaces around a key would otherwise split `${ key }` into several words.
+    let line = VARIABLE.replace_all(line, |captures: &Captures| {
+        format!("${{{}}}", captures[1].trim())
+    });
+
+    split_line(&line)?
+        .iter()
+        .map(|word| substitute_word(word))
+        .collect()
+}
+
+fn substitute_word(word: &str) -> CoreResult<String> {
+    let mut error = None;
+
+    let word = VARIABLE.replace_all(word, |captures: &Captures| {
+        let key = &captures[1];
+
+        match AppConfigManager::get::<Value>(key) {
+            Ok(Value::String(value)) => value,
+            Ok(value) => value.to_string(),
+            Err(_) => {
+                error.get_or_insert_with(|| format!("No config key \"{key}\" to substitute."));
+                String::new()
+            }
+        }
+    });
+
+    match error {
+        Some(error) => Err(CoreError::GeneralTaskError(error).into()),
+        None => Ok(word.into_owned()),
+    }
+}
+
+fn summary_output(
+    results: &[LineResult], summary: String, output_format: OutputFormat,
+) -> CommandOutput {
+    let mut text: Vec<String> = results
+        .iter()
+        .map(|result| {
+            let duration = result
+                .duration
+                .map(|duration| format!("{}ms", duration.as_millis()))
+                .unwrap_or_default();
+            let error = result
+                .error
+                .as_ref()
+                .map(|error| format!(" ({error})"))
+                .unwrap_or_default();
+
+            format!(
+                "{:<7} {duration:>8}  {}: {}{error}",
+                result.status.as_str(),
+                result.number,
+                result.line
+            )
+        })
+        .collect();
+    text.push(summary);
+
+    let rows = results
+        .iter()
+        .map(|result| {
+            let mut row = record(json!({
+                "line": result.number,
+                "command": result.line,
+                "status": result.status.as_str(),
+                "duration_ms": result.duration.map(|duration| duration.as_millis() as u64),
+                "error": result.error,
+            }));
+            if matches!(output_format, OutputFormat::Json | OutputFormat::Yaml) {
+                row.insert("output".to_string(), result.output.clone());
+            }
+
+            row
+        })
+        .collect();
+
+    CommandOutput::table(rows).with_text(text)
+}
+
+#[cfg(test)]
+mod script_tests {
+    use std::rc::Weak;
+
+    use cli::OutputFormat;
+    use configuration::{app_config::AppConfig, AppConfigManager};
+
+    use super::{run_lines, substitute, Status};
+    use crate::AppRuntime;
+
+    const SCRIPT: &str = "status\nfile-error\n# A comment.\n\nrun-script -\nstatus\n";
+
+    async fn statuses(continue_on_error: bool) -> Vec<(usize, Status)> {
+        let runtime = AppRuntime::new(Weak::new(), AppConfig::default());
+
+        run_lines(&runtime, SCRIPT, continue_on_error, OutputFormat::Json)
+            .await
+            .iter()
+            .map(|result| (result.number, result.status))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn first_failure_skips_the_rest() {
+        assert_eq!(
+            vec![
+                (1, Status::Ok),
+                (2, Status::Failed),
+                (5, Status::Skipped),
+                (6, Status::Skipped)
+            ],
+            statuses(false).await
+        );
+    }
+
+    #[tokio::test]
+    async fn continue_on_error_runs_every_line() {
+        // Scripts can't run scripts, which could otherwise run themselves forever.
+        assert_eq!(
+            vec![
+                (1, Status::Ok),
+                (2, Status::Failed),
+                (5, Status::Failed),
+                (6, Status::Ok)
+            ],
+            statuses(true).await
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_skips_the_rest() {
+        let runtime = AppRuntime::new(Weak::new(), AppConfig::default());
+        runtime.shutdown.request();
+
+        let results = run_lines(&runtime, SCRIPT, true, OutputFormat::Json).await;
+
+        assert!(results
+            .iter()
+            .all(|result| result.status == Status::Skipped));
+    }
+
+    #[test]
+    fn config_keys_are_substituted() {
+        assert_eq!(
+            vec!["logs", "show", "rust-starter-template", "--lines", "10"],
+            substitute("logs show ${program.name} --lines 10").unwrap()
+        );
+        assert_eq!(
+            vec!["demo-tasks", "false"],
+            substitute("demo-tasks ${ metrics.server_enabled }").unwrap()
+        );
+        assert!(substitute("demo-tasks ${no.such.key}").is_err());
+    }
+
+    #[test]
+    fn substituted_values_stay_one_word() {
+        AppConfigManager::set("script-test.title", r#"two "quoted" words --set x=1"#).unwrap();
+
+        assert_eq!(
+            vec!["config", "get", r#"two "quoted" words --set x=1"#, "a b"],
+            substitute("config get ${script-test.title} 'a b'").unwrap()
+        );
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use clap::{error::ErrorKind, parser::ValueSource, FromArgMatches};
+use cli::{aliases, completion_command, get_command, AppCommand, Cli, OutputFormat};
+use configuration::AppConfigManager;
+use parking_lot::Mutex;
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+use tracing::{debug, info, level_filters::LevelFilter, Level};
+use utils::{
+    core_types::{CoreError, CoreResult},
+    logging::FMT_SINK,
+    project_name_str,
+};
+
+use crate::{output::CommandOutput, AppRuntime};
+
+/// Commands the shell handles itself, rather than passing them to the runtime.
+const SPECIAL_COMMANDS: [(&str, &str); 4] = [
+    (":help", "List these commands."),
+    (
+        ":reload",
+        "Reload the configuration from its files and environment, and re-apply its logging settings.",
+    ),
+    (
+        ":log-level",
+        "Set the console log level, as in \":log-level debug\".",
+    ),
+    (":quit", "Leave the shell. \"exit\" and Ctrl-D work too."),
+];
+
+type ShellEditor = Editor<ShellHelper, DefaultHistory>;
+
+/// Read commands line by line and run each on `runtime`, until the user leaves.
+pub async fn run(runtime: &AppRuntime) -> CoreResult<CommandOutput> {
+    let mut editor = ShellEditor::new().map_err(readline_error)?;
+    editor.set_helper(Some(ShellHelper));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // There's no history file until a line has been entered.
+        if let Err(e) = editor.load_history(path) {
+            debug!("No shell history loaded from \"{}\": {e}", path.display());
+        }
+    }
+
+    // A read abandoned at shutdown never gets to take the terminal out of raw mode.
+    let restore_terminal = Arc::new(terminal_restorer());
+    runtime.shutdown.on_exit({
+        let restore_terminal = restore_terminal.clone();
+        move || restore_terminal()
+    });
+
+    println!("Enter a command such as \"demo-tasks 8\", or :help for the shell's own commands.");
+
+    let prompt = format!("{}> ", project_name_str!());
+    loop {
+        // Leave once shutdown is requested, rather than waiting at the prompt to be abandoned.
+        if runtime.shutdown.is_requested() {
+            break;
+        }
+
+        // Read on a thread of its own so shutdown isn't held up waiting for the user. The
+        // editor is handed back with the line, or left with the thread on shutdown.
+        let prompt = prompt.clone();
+        let read = runtime
+            .shutdown
+            .run_blocking(move || {
+                let line = editor.readline(&prompt);
+                (editor, line)
+            })
+            .await;
+        let line;
+        (editor, line) = match read {
+            Ok(read) => read,
+            Err(_) => {
+                restore_terminal();
+                break;
+            }
+        };
+
+        let line = match line {
+            Ok(line) => line,
+            // Ctrl-C abandons the line being typed, as in other shells.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(readline_error(e).into()),
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        // Saved as each line is entered, as the editor can't be waited on at shutdown.
+        editor.add_history_entry(line).map_err(readline_error)?;
+        if let Some(path) = &history {
+            save_history(&mut editor, path)?;
+        }
+
+        if matches!(line, ":quit" | "exit" | "quit") {
+            break;
+        }
+
+        if let Err(e) = run_line(runtime, line).await {
+            eprintln!("{e}");
+        }
+    }
+
+    Ok(CommandOutput::written())
+}
+
+fn save_history(editor: &mut ShellEditor, path: &Path) -> CoreResult<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(CoreError::from)?;
+    }
+
+    editor
+        .save_history(path)
+        .map_err(|e| readline_error(e).into())
+}
+
+/// Put back the terminal settings from when the shell started, if stdin is a terminal.
+#[cfg(unix)]
+fn terminal_restorer() -> impl Fn() + Send + Sync + 'static {
+    use nix::sys::termios::{tcgetattr, tcsetattr, SetArg};
+
+    let saved = tcgetattr(std::io::stdin()).ok().map(Mutex::new);
+    move || {
+        if let Some(saved) = &saved {
+            // This runs on the way out, so there's nothing to be done if it fails.
+            let _ = tcsetattr(std::io::stdin(), SetArg::TCSANOW, &saved.lock());
+            // The editor turns on bracketed paste along with raw mode.
+            let mut stdout = std::io::stdout();
+            let _ = stdout
+                .write_all(b"\x1b[?2004l")
+                .and_then(|_| stdout.flush());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_restorer() -> impl Fn() + Send + Sync + 'static {
+    || {}
+}
+
+/// The history file, under the XDG state directory where there is one.
+fn history_path() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join(project_name_str!()).join("shell_history"))
+}
+
+fn readline_error(e: ReadlineError) -> CoreError {
+    match e {
+        ReadlineError::Io(e) => CoreError::from(e),
+        e => CoreError::GeneralTaskError(format!("Could not read the command line: {e}")),
+    }
+}
+
+/// Run one line, parsed as if it followed the program name on the command line. Only
+/// `--output` is taken from a line's options; logging is set with `:log-level`.
+async fn run_line(runtime: &AppRuntime, line: &str) -> CoreResult<()> {
+    if let Some(command) = line.strip_prefix(':') {
+        return special_command(runtime, command);
+    }
+
+    let (command, output_format) = match parse_line(line)? {
+        Ok(parsed) => parsed,
+        // Usage errors and help are printed, and the shell carries on.
+        Err(e) => return e.print().map_err(|e| CoreError::from(e).into()),
+    };
+
+    if matches!(command, AppCommand::Shell) {
+        return Err(CoreError::GeneralTaskError("Already in the shell.".to_string()).into());
+    }
+
+    // Boxed, as the shell is itself run from `execute`.
+    let output_format = output_format.unwrap_or(runtime.output_format);
+    match Box::pin(runtime.execute(command, output_format)).await {
+        Ok(output) => output.write(output_format, &mut std::io::stdout()),
+        // `execute` has logged the failure, so it's only printed if the console won't show it.
+        Err(_) if console_shows_errors(runtime) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn console_shows_errors(runtime: &AppRuntime) -> bool {
+    runtime
+        .log_manager
+        .upgrade()
+        .and_then(|log_manager| log_manager.sink_level(FMT_SINK))
+        .is_some_and(|level| level >= LevelFilter::ERROR)
+}
+
+/// Parse `line` as if it followed the program name on the command line, with aliases
+/// expanded. The output format is only returned if the line sets it. Usage errors and
+/// help are returned as clap errors, for the caller to print or fail on.
+pub(crate) fn parse_line(
+    line: &str,
+) -> CoreResult<Result<(AppCommand, Option<OutputFormat>), clap::Error>> {
+    parse_words(split_line(line)?)
+}
+
+/// Split `line` into words, with shell-style quoting.
+pub(crate) fn split_line(line: &str) -> CoreResult<Vec<String>> {
+    shlex::split(line).ok_or_else(|| {
+        CoreError::GeneralTaskError("The line has an unclosed quote.".to_string()).into()
+    })
+}
+
+/// Parse `words` as if they followed the program name on the command line, like
+/// `parse_line`.
+pub(crate) fn parse_words(
+    words: Vec<String>,
+) -> CoreResult<Result<(AppCommand, Option<OutputFormat>), clap::Error>> {
+    let args = std::iter::once(project_name_str!().to_string())
+        .chain(words)
+        .map(OsString::from)
+        .collect();
+    let args = aliases::expand(&get_command(), args, &aliases::configured())?;
+
+    let mut command = get_command();
+    let matches = match command.try_get_matches_from_mut(args) {
+        Ok(matches) => matches,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    // The other options set up the whole program, so they'd be ignored on a single line.
+    let program_option = command.get_arguments().find(|arg| {
+        arg.get_id() != "output"
+            && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    });
+    if let Some(arg) = program_option {
+        let name = match (arg.get_long(), arg.get_short()) {
+            (Some(long), _) => format!("--{long}"),
+            (None, Some(short)) => format!("-{short}"),
+            (None, None) => arg.get_id().to_string(),
+        };
+        return Ok(Err(command.error(
+            ErrorKind::ArgumentConflict,
+            format!("{name} can only be given when the program starts. Only --output applies to a single command."),
+        )));
+    }
+    let cli = match Cli::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let output_format = match matches.value_source("output") {
+        Some(ValueSource::CommandLine) => Some(cli.output),
+        _ => None,
+    };
+
+    Ok(Ok((cli.command, output_format)))
+}
+
+/// A usage error as a one-line `CoreError`, for callers that can't show clap's full report.
+pub(crate) fn usage_error(e: &clap::Error) -> CoreError {
+    let message = e.to_string();
+    let message = message.lines().next().unwrap_or_default();
+
+    CoreError::GeneralTaskError(message.trim_start_matches("error: ").to_string())
+}
+
+fn special_command(runtime: &AppRuntime, command: &str) -> CoreResult<()> {
+    let mut words = command.split_whitespace();
+
+    match (words.next(), words.next(), words.next()) {
+        (Some("help"), None, None) => {
+            for (name, help) in SPECIAL_COMMANDS {
+                println!("  {name:<12}{help}");
+            }
+        }
+        (Some("reload"), None, None) => {
+            *runtime.app_config.write() = AppConfigManager::clone_to_app_config()?;
+            runtime.reload_logging()?;
+            info!("Configuration reloaded.");
+            println!("Configuration reloaded.");
+        }
+        (Some("log-level"), Some(level), None) => {
+            let level = level.parse::<Level>().map_err(|_| {
+                CoreError::GeneralTaskError(format!("\"{level}\" is not a log level."))
+            })?;
+
+            let set = runtime
+                .log_manager
+                .upgrade()
+                .is_some_and(|log_manager| log_manager.set_sink_level(FMT_SINK, level));
+            if !set {
+                return Err(CoreError::GeneralTaskError(
+                    "Console logging is off, so it has no level to set.".to_string(),
+                )
+                .into());
+            }
+
+            info!("Console log level set to {level}.");
+            println!("Console log level set to {level}.");
+        }
+        _ => {
+            return Err(CoreError::GeneralTaskError(format!(
+                "Unknown shell command \":{command}\". Enter :help for the list."
+            ))
+            .into())
+        }
+    }
+
+    Ok(())
+}
+
+/// Completes commands and arguments from the same definitions the command line uses.
+struct ShellHelper;
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self, line: &str, pos: usize, _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let current = if line.ends_with(char::is_whitespace) {
+            ""
+        } else {
+            words.pop().unwrap_or_default()
+        };
+        let start = pos - current.len();
+
+        if words.is_empty() && current.starts_with(':') {
+            let candidates = SPECIAL_COMMANDS
+                .iter()
+                .filter(|(name, _)| name.starts_with(current))
+                .map(|(name, _)| candidate(name))
+                .collect();
+
+            return Ok((start, candidates));
+        }
+
+        let args: Vec<OsString> = std::iter::once(project_name_str!())
+            .chain(words)
+            .chain(std::iter::once(current))
+            .map(OsString::from)
+            .collect();
+        let index = args.len() - 1;
+        let current_dir = std::env::current_dir().ok();
+
+        let candidates = clap_complete::engine::complete(
+            &mut completion_command(),
+            args,
+            index,
+            current_dir.as_deref(),
+        )
+        .unwrap_or_default()
+        .iter()
+        .map(|candidate| candidate.get_value().to_string_lossy())
+        .map(|value| self::candidate(&value))
+        .collect();
+
+        Ok((start, candidates))
+    }
+}
+
+fn candidate(value: &str) -> Pair {
+    Pair {
+        display: value.to_string(),
+        replacement: value.to_string(),
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+#[cfg(test)]
+mod shell_tests {
+    use std::rc::Rc;
+
+    use cli::{AppCommand, OutputFormat};
+    use configuration::app_config::AppConfig;
+    use rustyline::{completion::Completer, history::DefaultHistory, Context};
+    use tracing::{level_filters::LevelFilter, Level};
+    use utils::logging::{LoggingManager, FMT_SINK};
+
+    use super::{parse_line, special_command, ShellHelper};
+    use crate::AppRuntime;
+
+    #[test]
+    fn completion_follows_the_command_definitions() {
+        let history = DefaultHistory::new();
+        let complete = |line: &str| {
+            let (start, candidates) = ShellHelper
+                .complete(line, line.len(), &Context::new(&history))
+                .unwrap();
+            let values: Vec<String> = candidates
+                .into_iter()
+                .map(|candidate| candidate.replacement)
+                .collect();
+            (start, values)
+        };
+
+        assert_eq!((0, vec!["demo-tasks".to_string()]), complete("demo-t"));
+        assert!(complete("logs ").1.contains(&"tail".to_string()));
+        assert_eq!((0, vec![":reload".to_string()]), complete(":re"));
+        assert_eq!(5, complete("logs t").0);
+    }
+
+    #[test]
+    fn log_level_sets_the_console_sink_level() {
+        let mut log_manager = LoggingManager::new().with_fmt_logging(Level::INFO);
+        let _guard = log_manager.build_scoped().unwrap();
+        let log_manager = Rc::new(log_manager);
+        let runtime = AppRuntime::new(Rc::downgrade(&log_manager), AppConfig::default());
+
+        special_command(&runtime, "log-level debug").unwrap();
+
+        assert_eq!(Some(LevelFilter::DEBUG), log_manager.sink_level(FMT_SINK));
+        assert!(special_command(&runtime, "log-level loud").is_err());
+    }
+
+    #[test]
+    fn log_level_fails_without_console_logging() {
+        let runtime = AppRuntime::new(std::rc::Weak::new(), AppConfig::default());
+
+        assert!(special_command(&runtime, "log-level debug").is_err());
+    }
+
+    #[test]
+    fn output_is_only_overridden_when_the_line_sets_it() {
+        let (command, output) = parse_line("--output json config sources").unwrap().unwrap();
+        assert!(matches!(command, AppCommand::Config { .. }));
+        assert_eq!(Some(OutputFormat::Json), output);
+
+        let (_, output) = parse_line("config sources").unwrap().unwrap();
+        assert_eq!(None, output);
+    }
+
+    #[test]
+    fn program_options_are_refused_on_a_line() {
+        for line in [
+            "--set pool.concurrency=2 config sources",
+            "config sources -j 2",
+            "-v config sources",
+            "--quiet config sources",
+            "--log-format json config sources",
+        ] {
+            let e = parse_line(line).unwrap().unwrap_err();
+            assert!(
+                e.to_string()
+                    .contains("can only be given when the program starts"),
+                "{line}: {e}"
+            );
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::{fmt, future::Future, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utils::core_types::{CoreError, CoreResult};
+
+/// How long a command gets to finish once shutdown is requested, unless configured.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    Interrupt,
+    Terminate,
+    Quit,
+}
+
+impl Signal {
+    /// The signal's number, as used in the exit status.
+    fn number(self) -> i32 {
+        match self {
+            Self::Interrupt => 2,
+            Self::Quit => 3,
+            Self::Terminate => 15,
+        }
+    }
+}
+
+/// Run just before the process exits on a forced shutdown.
+type ExitHook = Box<dyn Fn() + Send + Sync>;
+
+/// Coordinates shutting down. Once shutdown is requested, commands should stop taking on
+/// new work and finish what they're doing, and are abandoned if they haven't within the
+/// grace period. A forced shutdown abandons them straight away.
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: CancellationToken,
+    forced: CancellationToken,
+    grace_period: Duration,
+    exit_hooks: Arc<Mutex<Vec<ExitHook>>>,
+    /// The last signal received, which decides the exit status.
+    signal: Arc<Mutex<Option<Signal>>>,
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("requested", &self.requested)
+            .field("forced", &self.forced)
+            .field("grace_period", &self.grace_period)
+            .field("signal", &self.signal.lock())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new(DEFAULT_GRACE_PERIOD)
+    }
+}
+
+impl Shutdown {
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            requested: CancellationToken::new(),
+            forced: CancellationToken::new(),
+            grace_period,
+            exit_hooks: Arc::default(),
+            signal: Arc::default(),
+        }
+    }
+
+    /// Run `hook` before exiting on a forced shutdown, such as to flush buffered logs.
+    /// Hooks run in the order they were added, and are shared by every clone.
+    pub fn on_exit(&self, hook: impl Fn() + Send + Sync + 'static) {
+        self.exit_hooks.lock().push(Box::new(hook));
+    }
+
+    /// Cancelled once shutdown is requested, for tasks to watch.
+    pub fn token(&self) -> CancellationToken {
+        self.requested.clone()
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.is_cancelled()
+    }
+
+    pub fn is_forced(&self) -> bool {
+        self.forced.is_cancelled()
+    }
+
+    pub fn request(&self) {
+        self.requested.cancel();
+    }
+
+    pub fn force(&self) {
+        self.requested.cancel();
+        self.forced.cancel();
+    }
+
+    /// The exit status for a shutdown: 128 plus the number of the signal behind it, as
+    /// shells report, or 1 if it wasn't a signal.
+    pub fn exit_code(&self) -> i32 {
+        self.signal.lock().map_or(1, |signal| 128 + signal.number())
+    }
+
+    /// Force shutdown and exit straight away, with the status for the signal. The command
+    /// isn't waited on, as it may be blocked where it can't be abandoned.
+    fn exit_now(&self) -> ! {
+        self.force();
+        for hook in self.exit_hooks.lock().iter() {
+            hook();
+        }
+
+        std::process::exit(self.exit_code());
+    }
+
+    /// Handle signals from now until exit. SIGINT and SIGTERM request shutdown, and SIGQUIT
+    /// or a second SIGINT forces it, exiting from the signal handler.
+    pub fn listen_for_signals(&self) -> CoreResult<()> {
+        let mut signals = Signals::new()?;
+        let shutdown = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let signal = signals.recv().await;
+                *shutdown.signal.lock() = Some(signal);
+
+                match signal {
+                    Signal::Quit => {
+                        warn!("Quit. Exiting without waiting for the command to finish.");
+                        shutdown.exit_now();
+                    }
+                    Signal::Interrupt if shutdown.is_requested() => {
+                        warn!(
+                            "Interrupted again. Exiting without waiting for the command to finish."
+                        );
+                        shutdown.exit_now();
+                    }
+                    signal => {
+                        info!(
+                            "{}. Shutting down, waiting up to {}ms for the command to finish. \
+                             Press Ctrl-C again to exit now.",
+                            if signal == Signal::Interrupt {
+                                "Interrupted"
+                            } else {
+                                "Terminated"
+                            },
+                            shutdown.grace_period.as_millis()
+                        );
+                        shutdown.request();
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Run `future` to the end, unless shutdown is forced or it's still running when the
+    /// grace period runs out.
+    pub async fn run<T>(&self, future: impl Future<Output = CoreResult<T>>) -> CoreResult<T> {
+        tokio::pin!(future);
+
+        tokio::select! {
+            result = &mut future => return result,
+            _ = self.requested.cancelled() => {}
+        }
+
+        tokio::select! {
+            result = &mut future => result,
+            _ = self.forced.cancelled() => {
+                Err(CoreError::Interrupted("The command was abandoned.".to_string()).into())
+            }
+            _ = tokio::time::sleep(self.grace_period) => Err(CoreError::Interrupted(format!(
+                "The command didn't finish within the {}ms grace period.",
+                self.grace_period.as_millis()
+            ))
+            .into()),
+        }
+    }
+
+    /// Run blocking `work`, such as reading the terminal, on a thread of its own, failing
+    /// if shutdown is requested first. Unlike `spawn_blocking`, the thread is left behind
+    /// rather than waited on at exit, as work waiting on the user may never finish.
+    pub async fn run_blocking<T: Send + 'static>(
+        &self, work: impl FnOnce() -> T + Send + 'static,
+    ) -> CoreResult<T> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        std::thread::spawn(move || {
+            // The receiver is gone if shutdown came first, so there's no one to tell.
+            let _ = sender.send(work());
+        });
+
+        tokio::select! {
+            result = receiver => result.map_err(|_| {
+                CoreError::GeneralTaskError("The blocking work panicked.".to_string()).into()
+            }),
+            _ = self.requested.cancelled() => {
+                Err(CoreError::Interrupted("Shutting down.".to_string()).into())
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+    quit: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> CoreResult<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt()).map_err(CoreError::from)?,
+            terminate: signal(SignalKind::terminate()).map_err(CoreError::from)?,
+            quit: signal(SignalKind::quit()).map_err(CoreError::from)?,
+        })
+    }
+
+    async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            Some(()) = self.interrupt.recv() => Signal::Interrupt,
+            Some(()) = self.terminate.recv() => Signal::Terminate,
+            Some(()) = self.quit.recv() => Signal::Quit,
+            // The signal driver only goes away as the runtime shuts down.
+            else => std::future::pending().await,
+        }
+    }
+}
+
+/// Elsewhere, only Ctrl-C can be handled.
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> CoreResult<Self> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) -> Signal {
+        let _ = tokio::signal::ctrl_c().await;
+
+        Signal::Interrupt
+    }
+}
+
+#[cfg(test)]
+mod shutdown_tests {
+    use std::time::Duration;
+
+    use super::{Shutdown, Signal};
+
+    #[tokio::test(start_paused = true)]
+    async fn commands_get_the_grace_period_to_finish() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let token = shutdown.token();
+        shutdown.request();
+
+        // Winds down within the grace period once asked to.
+        let finished = shutdown.run(async move {
+            token.cancelled().await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok("finished")
+        });
+        assert_eq!("finished", finished.await.unwrap());
+
+        // Never finishes, so is abandoned.
+        let abandoned = shutdown.run(std::future::pending::<utils::core_types::CoreResult<()>>());
+        assert!(abandoned.await.is_err());
+
+        shutdown.force();
+        let forced = shutdown.run(async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        });
+        assert!(forced.await.is_err());
+    }
+
+    #[test]
+    fn exit_code_follows_the_signal() {
+        let shutdown = Shutdown::default();
+        assert_eq!(1, shutdown.exit_code());
+
+        for (signal, code) in [
+            (Signal::Interrupt, 130),
+            (Signal::Terminate, 143),
+            (Signal::Quit, 131),
+        ] {
+            *shutdown.signal.lock() = Some(signal);
+            assert_eq!(code, shutdown.exit_code());
+        }
+    }
+
+    #[tokio::test]
+    async fn blocking_work_is_left_behind_on_shutdown() {
+        let shutdown = Shutdown::default();
+
+        assert_eq!(2, shutdown.run_blocking(|| 1 + 1).await.unwrap());
+
+        let stuck = shutdown.run_blocking(|| loop {
+            std::thread::park();
+        });
+        shutdown.request();
+        assert!(stuck.await.is_err());
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Display,
+    future::Future,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use configuration::app_config;
+use parking_lot::Mutex;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use utils::{
+    core_types::{CoreError, CoreResult},
+    random::Xorshift,
+};
+
+use crate::{backoff::Backoff, shutdown::Shutdown, tasks::TaskRegistry};
+
+/// When a supervised task is started again after it stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestartPolicy {
+    Never,
+    #[default]
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    fn restarts(self, failed: bool) -> bool {
+        match self {
+            Self::Never => false,
+            Self::OnFailure => failed,
+            Self::Always => true,
+        }
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::Never),
+            "on-failure" => Ok(Self::OnFailure),
+            "always" => Ok(Self::Always),
+            _ => Err(CoreError::SupervisorConfigError(format!(
+                "Unknown restart policy \"{s}\". Use never, on-failure or always."
+            ))),
+        }
+    }
+}
+
+impl Display for RestartPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Never => "never",
+            Self::OnFailure => "on-failure",
+            Self::Always => "always",
+        })
+    }
+}
+
+/// How often a task may restart before the supervisor gives up on it: at most
+/// `max_restarts` times within any `window`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Intensity {
+    pub max_restarts: u32,
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RestartStrategy {
+    pub policy: RestartPolicy,
+    pub backoff: Backoff,
+    pub intensity: Intensity,
+}
+
+impl RestartStrategy {
+    pub fn from_config(config: &app_config::Supervisor) -> CoreResult<Self> {
+        if !(0.0..=1.0).contains(&config.backoff_jitter) {
+            return Err(CoreError::SupervisorConfigError(format!(
+                "The backoff jitter must be from 0 to 1, not {}.",
+                config.backoff_jitter
+            ))
+            .into());
+        }
+
+        Ok(Self {
+            policy: config.restart_policy.parse()?,
+            backoff: Backoff {
+                initial: Duration::from_millis(config.backoff_initial_ms),
+                max: Duration::from_millis(config.backoff_max_ms),
+                jitter: config.backoff_jitter,
+            },
+            intensity: Intensity {
+                max_restarts: config.max_restarts,
+                window: Duration::from_millis(config.max_restarts_window_ms),
+            },
+        })
+    }
+
+    pub fn with_policy(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupervisedState {
+    Running,
+    /// Waiting out the backoff before restarting.
+    Restarting,
+    /// Stopped by its policy or by shutdown.
+    Stopped,
+    /// Stopped after failing, without being restarted.
+    Failed,
+    /// Given up on after restarting too often, shutting the application down.
+    Escalated,
+}
+
+impl SupervisedState {
+    fn is_active(self) -> bool {
+        matches!(self, Self::Running | Self::Restarting)
+    }
+}
+
+impl Display for SupervisedState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Running => "running",
+            Self::Restarting => "restarting",
+            Self::Stopped => "stopped",
+            Self::Failed => "failed",
+            Self::Escalated => "escalated",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SupervisedTask {
+    pub name: String,
+    pub policy: RestartPolicy,
+    pub state: SupervisedState,
+    pub restarts: u32,
+    /// When the task last started, or went into its current state if it isn't running.
+    pub since: Instant,
+    pub last_error: Option<String>,
+}
+
+/// Runs named long-running tasks, restarting them by their `RestartStrategy`. Tasks are
+/// told to stop through the token they're given once shutdown is requested, and a task that
+/// restarts too often requests shutdown itself.
+#[derive(Debug)]
+pub struct Supervisor {
+    shutdown: Shutdown,
+    tasks: Arc<TaskRegistry>,
+    supervised: Arc<Mutex<BTreeMap<String, SupervisedTask>>>,
+    handles: Mutex<JoinSet<()>>,
+}
+
+impl Supervisor {
+    pub fn new(shutdown: Shutdown, tasks: Arc<TaskRegistry>) -> Self {
+        Self {
+            shutdown,
+            tasks,
+            supervised: Arc::default(),
+            handles: Mutex::default(),
+        }
+    }
+
+    /// Start supervising the task `start` makes, calling it again for each restart. The
+    /// task should return once its token is cancelled.
+    pub fn spawn<F, Fut>(
+        &self, name: impl Into<String>, strategy: RestartStrategy, start: F,
+    ) -> CoreResult<()>
+    where
+        F: Fn(CancellationToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CoreResult<()>> + Send + 'static,
+    {
+        let name = name.into();
+        {
+            let mut supervised = self.supervised.lock();
+            if supervised
+                .get(&name)
+                .is_some_and(|task| task.state.is_active())
+            {
+                return Err(CoreError::GeneralTaskError(format!(
+                    "A task named \"{name}\" is already supervised."
+                ))
+                .into());
+            }
+
+            supervised.insert(
+                name.clone(),
+                SupervisedTask {
+                    name: name.clone(),
+                    policy: strategy.policy,
+                    state: SupervisedState::Running,
+                    restarts: 0,
+                    since: Instant::now(),
+                    last_error: None,
+                },
+            );
+        }
+
+        let child = Child {
+            name,
+            strategy,
+            shutdown: self.shutdown.clone(),
+            tasks: self.tasks.clone(),
+            supervised: self.supervised.clone(),
+        };
+        self.handles.lock().spawn(child.supervise(start));
+
+        Ok(())
+    }
+
+    /// Every supervised task, by name.
+    pub fn snapshot(&self) -> Vec<SupervisedTask> {
+        self.supervised.lock().values().cloned().collect()
+    }
+
+    /// Wait until every task spawned so far has stopped for good, returning an error if
+    /// any was given up on.
+    pub async fn wait(&self) -> CoreResult<()> {
+        let mut handles = std::mem::take(&mut *self.handles.lock());
+        while handles.join_next().await.is_some() {}
+
+        let escalated: Vec<String> = self
+            .snapshot()
+            .into_iter()
+            .filter(|task| task.state == SupervisedState::Escalated)
+            .map(|task| task.name)
+            .collect();
+
+        if escalated.is_empty() {
+            Ok(())
+        } else {
+            Err(CoreError::GeneralTaskError(format!(
+                "Gave up on supervised tasks that kept failing: {}.",
+                escalated.join(", ")
+            ))
+            .into())
+        }
+    }
+}
+
+/// The supervision of one task, moved into the task restarting it.
+struct Child {
+    name: String,
+    strategy: RestartStrategy,
+    shutdown: Shutdown,
+    tasks: Arc<TaskRegistry>,
+    supervised: Arc<Mutex<BTreeMap<String, SupervisedTask>>>,
+}
+
+impl Child {
+    async fn supervise<F, Fut>(self, start: F)
+    where
+        F: Fn(CancellationToken) -> Fut,
+        Fut: Future<Output = CoreResult<()>> + Send + 'static,
+    {
+        let token = self.shutdown.token();
+        let labels = [("task", self.name.as_str())];
+        let running = metrics::gauge(
+            "app_supervised_task_running",
+            "Whether a supervised task is running.",
+            &labels,
+        );
+        let random = Xorshift::new();
+        let mut recent_restarts = VecDeque::new();
+
+        loop {
+            info!("Starting supervised task \"{}\".", self.name);
+            self.update(SupervisedState::Running, None);
+            running.set(1.0);
+
+            // Run on its own so a panic is reported as a failure rather than ending
+            // supervision.
+            let outcome = tokio::spawn(self.tasks.track(self.name.clone(), start(token.clone())))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result.map_err(|e| e.to_string()));
+            running.set(0.0);
+
+            let failed = outcome.is_err();
+            if let Err(e) = outcome {
+                warn!("Supervised task \"{}\" failed: {e}", self.name);
+                self.update(SupervisedState::Failed, Some(e));
+            }
+
+            if token.is_cancelled() || !self.strategy.policy.restarts(failed) {
+                info!("Supervised task \"{}\" stopped.", self.name);
+                if !failed {
+                    self.update(SupervisedState::Stopped, None);
+                }
+                return;
+            }
+
+            let now = Instant::now();
+            let intensity = self.strategy.intensity;
+            recent_restarts.retain(|restart| now.duration_since(*restart) < intensity.window);
+            if recent_restarts.len() >= intensity.max_restarts as usize {
+                error!(
+                    "Supervised task \"{}\" restarted {} times within {}ms. Giving up and shutting down.",
+                    self.name,
+                    recent_restarts.len(),
+                    intensity.window.as_millis()
+                );
+                metrics::counter(
+                    "app_supervised_escalations_total",
+                    "Supervised tasks given up on for restarting too often.",
+                    &labels,
+                )
+                .increment(1);
+                self.update(SupervisedState::Escalated, None);
+                self.shutdown.request();
+                return;
+            }
+
+            // Restarts close together back off further, and the delay resets once a task
+            // has run for a whole window.
+            let delay = self
+                .strategy
+                .backoff
+                .delay(recent_restarts.len() as u32, random.next_f64());
+            recent_restarts.push_back(now);
+
+            info!(
+                "Restarting supervised task \"{}\" in {}ms.",
+                self.name,
+                delay.as_millis()
+            );
+            metrics::counter(
+                "app_supervised_restarts_total",
+                "Restarts of supervised tasks.",
+                &labels,
+            )
+            .increment(1);
+            self.update(SupervisedState::Restarting, None);
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = token.cancelled() => {
+                    info!("Supervised task \"{}\" stopped.", self.name);
+                    self.update(SupervisedState::Stopped, None);
+                    return;
+                }
+            }
+
+            if let Some(task) = self.supervised.lock().get_mut(&self.name) {
+                task.restarts += 1;
+            }
+        }
+    }
+
+    fn update(&self, state: SupervisedState, error: Option<String>) {
+        if let Some(task) = self.supervised.lock().get_mut(&self.name) {
+            task.state = state;
+            task.since = Instant::now();
+            if error.is_some() {
+                task.last_error = error;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod supervisor_tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use utils::core_types::CoreError;
+
+    use super::{Intensity, RestartPolicy, RestartStrategy, SupervisedState, Supervisor};
+    use crate::{backoff::Backoff, shutdown::Shutdown};
+
+    fn strategy(policy: RestartPolicy, max_restarts: u32) -> RestartStrategy {
+        RestartStrategy {
+            policy,
+            backoff: Backoff {
+                initial: Duration::from_millis(100),
+                max: Duration::from_secs(1),
+                jitter: 0.0,
+            },
+            intensity: Intensity {
+                max_restarts,
+                window: Duration::from_secs(60),
+            },
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_tasks_restart_until_they_escalate() {
+        let shutdown = Shutdown::default();
+        let supervisor = Supervisor::new(shutdown.clone(), Arc::default());
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let counter = runs.clone();
+        supervisor
+            .spawn("flaky", strategy(RestartPolicy::OnFailure, 3), move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                async { Err(CoreError::GeneralTaskError("Failed".to_string()).into()) }
+            })
+            .unwrap();
+
+        assert!(supervisor.wait().await.is_err());
+        assert_eq!(4, runs.load(Ordering::Relaxed));
+        assert!(shutdown.is_requested());
+
+        let task = &supervisor.snapshot()[0];
+        assert_eq!(SupervisedState::Escalated, task.state);
+        assert_eq!(3, task.restarts);
+        assert_eq!(
+            Some("General Task Error: Failed"),
+            task.last_error.as_deref()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tasks_stop_by_policy_or_shutdown() {
+        let shutdown = Shutdown::default();
+        let supervisor = Supervisor::new(shutdown.clone(), Arc::default());
+
+        supervisor
+            .spawn("once", strategy(RestartPolicy::OnFailure, 3), |_| async {
+                Ok(())
+            })
+            .unwrap();
+        supervisor
+            .spawn(
+                "forever",
+                strategy(RestartPolicy::Always, 3),
+                |token| async move {
+                    token.cancelled().await;
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        shutdown.request();
+        supervisor.wait().await.unwrap();
+
+        assert!(supervisor
+            .snapshot()
+            .iter()
+            .all(|task| task.state == SupervisedState::Stopped && task.restarts == 0));
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+/// How many finished tasks are remembered, so a long job doesn't grow the registry forever.
+const MAX_FINISHED_TASKS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Running,
+    Finished,
+    /// The task panicked.
+    Failed,
+    /// The task was dropped before finishing, such as by `JoinSet::abort_all`.
+    Cancelled,
+}
+
+impl std::fmt::Display for TaskState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Running => "running",
+            Self::Finished => "finished",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: String,
+    pub state: TaskState,
+    pub started: Instant,
+    pub ended: Option<Instant>,
+}
+
+impl TaskInfo {
+    /// How long the task ran, or has been running so far.
+    pub fn duration(&self) -> Duration {
+        self.ended.unwrap_or_else(Instant::now) - self.started
+    }
+}
+
+/// The tasks the runtime has spawned, with their state and timings, for anything that
+/// wants to watch them.
+#[derive(Debug, Default)]
+pub struct TaskRegistry {
+    next_id: AtomicU64,
+    tasks: Mutex<BTreeMap<u64, TaskInfo>>,
+}
+
+impl TaskRegistry {
+    /// Wrap `future` so it's listed under `name` from now until it finishes or is dropped.
+    pub fn track<F: Future>(
+        self: &Arc<Self>, name: impl Into<String>, future: F,
+    ) -> impl Future<Output = F::Output> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.tasks.lock().insert(
+            id,
+            TaskInfo {
+                id,
+                name: name.into(),
+                state: TaskState::Running,
+                started: Instant::now(),
+                ended: None,
+            },
+        );
+
+        let guard = TaskGuard {
+            registry: self.clone(),
+            id,
+        };
+
+        async move {
+            let output = future.await;
+            guard.end(TaskState::Finished);
+            output
+        }
+    }
+
+    /// Every remembered task, oldest first.
+    pub fn snapshot(&self) -> Vec<TaskInfo> {
+        self.tasks.lock().values().cloned().collect()
+    }
+
+    fn end(&self, id: u64, state: TaskState) {
+        let mut tasks = self.tasks.lock();
+
+        if let Some(task) = tasks.get_mut(&id).filter(|task| task.ended.is_none()) {
+            task.state = state;
+            task.ended = Some(Instant::now());
+        }
+
+        let finished: Vec<u64> = tasks
+            .values()
+            .filter(|task| task.ended.is_some())
+            .map(|task| task.id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(MAX_FINISHED_TASKS))
+        {
+            tasks.remove(id);
+        }
+    }
+}
+
+/// Ends the task when dropped, so tasks that panic or are cancelled aren't left running.
+struct TaskGuard {
+    registry: Arc<TaskRegistry>,
+    id: u64,
+}
+
+impl TaskGuard {
+    fn end(self, state: TaskState) {
+        self.registry.end(self.id, state);
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        let state = if std::thread::panicking() {
+            TaskState::Failed
+        } else {
+            TaskState::Cancelled
+        };
+
+        // Does nothing if the task already ended normally.
+        self.registry.end(self.id, state);
+    }
+}
+
+#[cfg(test)]
+mod tasks_tests {
+    use std::sync::Arc;
+
+    use super::{TaskRegistry, TaskState};
+
+    #[tokio::test]
+    async fn tasks_are_tracked_until_they_end() {
+        let registry = Arc::new(TaskRegistry::default());
+
+        let finished = registry.track("finished", async { 1 });
+        let cancelled = registry.track("cancelled", std::future::pending::<()>());
+        let running = registry.track("running", std::future::pending::<()>());
+
+        let states = || {
+            registry
+                .snapshot()
+                .into_iter()
+                .map(|task| (task.name, task.state))
+                .collect::<Vec<_>>()
+        };
+        assert!(states()
+            .iter()
+            .all(|(_, state)| *state == TaskState::Running));
+
+        assert_eq!(1, finished.await);
+        drop(cancelled);
+
+        assert_eq!(
+            vec![
+                ("finished".to_string(), TaskState::Finished),
+                ("cancelled".to_string(), TaskState::Cancelled),
+                ("running".to_string(), TaskState::Running),
+            ],
+            states()
+        );
+        drop(running);
+    }
+}
@@ -0,0 +1,494 @@
+use std::{
+    cmp::Reverse,
+    rc::{Rc, Weak},
+    sync::Arc,
+    time::Duration,
+};
+
+use cli::{AppCommand, DocsSubCommand, LogsSubCommand, OutputFormat};
+use configuration::app_config::AppConfig;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table},
+    Frame,
+};
+use tracing::{info, Level};
+use tracing_subscriber::filter::Targets;
+use utils::{
+    core_types::{CoreError, CoreResult},
+    logging::{
+        format::{ColorMode, LogFormat, LogStyle},
+        redaction::Redactor,
+        ring_buffer::{RingBuffer, RingBufferMakeWriter},
+        LoggingManager, FMT_SINK,
+    },
+};
+
+use crate::{
+    output::{self, CommandOutput},
+    redactor_from_config,
+    shell::{parse_words, usage_error},
+    tasks::{TaskRegistry, TaskState},
+    AppRuntime,
+};
+
+/// The sink feeding the dashboard's log pane.
+pub const TUI_SINK: &str = "tui";
+/// How many log lines the dashboard keeps.
+const LOG_CAPACITY: usize = 5000;
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+const LEVEL_KEYS: [(char, Level); 5] = [
+    ('1', Level::ERROR),
+    ('2', Level::WARN),
+    ('3', Level::INFO),
+    ('4', Level::DEBUG),
+    ('5', Level::TRACE),
+];
+
+/// Show the dashboard until the user quits, running `command` meanwhile if one is given.
+/// If the command fails, its error is returned once the dashboard closes.
+pub async fn run(runtime: &AppRuntime, command: Vec<String>) -> CoreResult<CommandOutput> {
+    let command = if command.is_empty() {
+        None
+    } else {
+        match parse_words(command)? {
+            Ok((command, _)) if uses_the_terminal(&command) => {
+                let name = match &command {
+                    AppCommand::Plugin(args) => args.first().map_or("plugin", String::as_str),
+                    command => command.name(),
+                };
+                return Err(CoreError::GeneralTaskError(format!(
+                    "\"{name}\" uses the terminal itself, so it can't be run inside the dashboard."
+                ))
+                .into());
+            }
+            Ok((command, _)) => Some(command),
+            Err(e) => return Err(usage_error(&e).into()),
+        }
+    };
+
+    let log_manager = runtime.log_manager.upgrade().ok_or_else(|| {
+        CoreError::GeneralTaskError("Logging has already been shut down.".to_string())
+    })?;
+    let level = log_manager
+        .sink_level(FMT_SINK)
+        .and_then(|level| level.into_level())
+        .unwrap_or(Level::INFO);
+
+    let redactor = redactor_from_config(&runtime.app_config.read().logging.redaction)?;
+    let mut dashboard = Dashboard::new(runtime.log_manager.clone(), runtime.tasks.clone(), level);
+    let _logging = LoggingGuard::attach(log_manager, dashboard.logs.clone(), level);
+
+    let mut terminal = ratatui::try_init().map_err(CoreError::from)?;
+    let _terminal = TerminalGuard;
+
+    let command_name = command.as_ref().map(ToString::to_string);
+    let mut running = command.is_some();
+    // Boxed, as the dashboard is itself run from `execute`.
+    let mut command = Box::pin(async {
+        match command {
+            Some(command) => runtime.execute(command, OutputFormat::Text).await,
+            None => std::future::pending().await,
+        }
+    });
+    if let Some(name) = &command_name {
+        dashboard.status = format!("Running \"{name}\".");
+    }
+
+    let mut failure = None;
+    let shutdown = runtime.shutdown.token();
+    let mut frames = tokio::time::interval(FRAME_INTERVAL);
+    loop {
+        terminal
+            .draw(|frame| dashboard.draw(frame, &runtime.app_config.read(), &redactor))
+            .map_err(CoreError::from)?;
+
+        tokio::select! {
+            result = &mut command, if running => {
+                running = false;
+                let name = command_name.as_deref().unwrap_or_default();
+                dashboard.status = match result {
+                    Ok(output) => {
+                        // The result goes in the log pane, as the dashboard has the screen.
+                        for line in output.render(OutputFormat::Text)?.lines() {
+                            dashboard.logs.push(format!("output: {line}"));
+                        }
+                        format!("\"{name}\" finished.")
+                    }
+                    Err(e) => {
+                        let status = format!("\"{name}\" failed: {e}");
+                        failure = Some(e);
+                        status
+                    }
+                };
+            }
+            _ = frames.tick() => {}
+            // Any command is left to wind down first.
+            _ = shutdown.cancelled(), if !running => {
+                return failure.map_or(Ok(CommandOutput::written()), Err)
+            }
+        }
+
+        while event::poll(Duration::ZERO).map_err(CoreError::from)? {
+            if let Event::Key(key) = event::read().map_err(CoreError::from)? {
+                if key.kind == KeyEventKind::Press && !dashboard.handle_key(key) {
+                    return failure.map_or(Ok(CommandOutput::written()), Err);
+                }
+            }
+        }
+    }
+}
+
+/// Whether `command` reads or writes the terminal itself rather than returning its result,
+/// which would draw over the dashboard.
+fn uses_the_terminal(command: &AppCommand) -> bool {
+    match command {
+        AppCommand::Shell | AppCommand::Tui { .. } | AppCommand::RunScript { .. } => true,
+        AppCommand::Plugin(_) => true,
+        AppCommand::Completion { subcommand, .. } => subcommand.shell().is_some(),
+        AppCommand::Docs { subcommand } => matches!(subcommand, DocsSubCommand::Markdown),
+        AppCommand::Logs { subcommand } => {
+            matches!(subcommand, LogsSubCommand::Tail { follow: true, .. })
+        }
+        _ => false,
+    }
+}
+
+/// Feeds the log pane while the dashboard is up, with the console silenced so it doesn't
+/// write over the screen. Both are put back when dropped.
+struct LoggingGuard {
+    log_manager: Rc<LoggingManager>,
+    console: Option<Targets>,
+}
+
+impl LoggingGuard {
+    fn attach(log_manager: Rc<LoggingManager>, logs: Arc<RingBuffer>, level: Level) -> Self {
+        let format = LogFormat {
+            style: LogStyle::Compact,
+            color: ColorMode::Never,
+            ..LogFormat::default()
+        };
+        log_manager.attach_sink(
+            TUI_SINK,
+            level,
+            format.layer(RingBufferMakeWriter::new(logs), false),
+        );
+
+        let console = log_manager.sink_filter(FMT_SINK);
+        log_manager.set_sink_filter(FMT_SINK, Targets::new());
+
+        Self {
+            log_manager,
+            console,
+        }
+    }
+}
+
+impl Drop for LoggingGuard {
+    fn drop(&mut self) {
+        self.log_manager.detach_sink(TUI_SINK);
+        if let Some(console) = self.console.take() {
+            self.log_manager.set_sink_filter(FMT_SINK, console);
+        }
+    }
+}
+
+/// Gives the terminal back, even if the dashboard fails.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+struct Dashboard {
+    log_manager: Weak<LoggingManager>,
+    tasks: Arc<TaskRegistry>,
+    logs: Arc<RingBuffer>,
+    level: Level,
+    /// Only log lines containing this are shown, ignoring case.
+    filter: String,
+    editing_filter: bool,
+    /// How many lines the log pane is scrolled up from the newest. 0 follows new lines.
+    scroll: usize,
+    status: String,
+}
+
+impl Dashboard {
+    fn new(log_manager: Weak<LoggingManager>, tasks: Arc<TaskRegistry>, level: Level) -> Self {
+        Self {
+            log_manager,
+            tasks,
+            logs: Arc::new(RingBuffer::new(LOG_CAPACITY)),
+            level,
+            filter: String::new(),
+            editing_filter: false,
+            scroll: 0,
+            status: String::new(),
+        }
+    }
+
+    /// Act on a key press, returning `false` once the user quits.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+
+        if self.editing_filter {
+            match key.code {
+                KeyCode::Char(c) => self.filter.push(c),
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Enter | KeyCode::Esc => self.editing_filter = false,
+                _ => {}
+            }
+            self.scroll = 0;
+
+            return true;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('/') => self.editing_filter = true,
+            KeyCode::Char(c) => {
+                if let Some((_, level)) = LEVEL_KEYS.iter().find(|(key, _)| *key == c) {
+                    self.set_level(*level);
+                }
+            }
+            KeyCode::Up => self.scroll += 1,
+            KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageUp => self.scroll += 10,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Home => self.scroll = usize::MAX,
+            KeyCode::End => self.scroll = 0,
+            _ => {}
+        }
+
+        true
+    }
+
+    fn set_level(&mut self, level: Level) {
+        self.level = level;
+
+        if let Some(log_manager) = self.log_manager.upgrade() {
+            log_manager.set_sink_level(TUI_SINK, level);
+        }
+        info!("Dashboard log level set to {level}.");
+    }
+
+    fn draw(&mut self, frame: &mut Frame, config: &AppConfig, redactor: &Redactor) {
+        let [body, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [top, logs] =
+            Layout::vertical([Constraint::Percentage(40), Constraint::Min(0)]).areas(body);
+        let [tasks, config_area] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(top);
+
+        frame.render_widget(self.tasks_table(), tasks);
+
+        let mut config = serde_json::to_value(config).unwrap_or_default();
+        output::redact(&mut config, redactor, &[]);
+        let config = serde_json::to_string_pretty(&config).unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(config).block(Block::bordered().title("Config")),
+            config_area,
+        );
+
+        let height = usize::from(logs.height.saturating_sub(2));
+        let (lines, title) = self.visible_logs(height);
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            logs,
+        );
+
+        let status_line = if self.editing_filter {
+            format!("Filter: {}_", self.filter)
+        } else {
+            format!(
+                "{}  [q] quit  [/] filter  [1-5] level  [Up/Down/PgUp/PgDn] scroll  [End] follow",
+                self.status
+            )
+        };
+        frame.render_widget(
+            Paragraph::new(status_line).style(Style::default().add_modifier(Modifier::REVERSED)),
+            status,
+        );
+    }
+
+    fn tasks_table(&self) -> Table<'static> {
+        let mut tasks = self.tasks.snapshot();
+        let running = tasks
+            .iter()
+            .filter(|task| task.state == TaskState::Running)
+            .count();
+        // Running tasks first, then the most recent.
+        tasks.sort_by_key(|task| (task.state != TaskState::Running, Reverse(task.started)));
+
+        let rows = tasks.into_iter().map(|task| {
+            let style = match task.state {
+                TaskState::Running => Style::default().fg(Color::Green),
+                TaskState::Finished => Style::default(),
+                TaskState::Failed => Style::default().fg(Color::Red),
+                TaskState::Cancelled => Style::default().fg(Color::Yellow),
+            };
+            let duration = format!("{:.1}s", task.duration().as_secs_f64());
+            Row::new([task.name, task.state.to_string(), duration]).style(style)
+        });
+
+        Table::new(
+            rows,
+            [
+                Constraint::Min(12),
+                Constraint::Length(10),
+                Constraint::Length(9),
+            ],
+        )
+        .header(
+            Row::new(["Task", "State", "Duration"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title(format!("Tasks ({running} running)")))
+    }
+
+    /// The log lines that fit in `height`, after filtering and scrolling, with a title
+    /// describing what's shown.
+    fn visible_logs(&mut self, height: usize) -> (Vec<Line<'static>>, String) {
+        let filter = self.filter.to_lowercase();
+        let lines: Vec<String> = self
+            .logs
+            .snapshot()
+            .iter()
+            .flat_map(|event| event.lines())
+            .filter(|line| filter.is_empty() || line.to_lowercase().contains(&filter))
+            .map(str::to_string)
+            .collect();
+
+        self.scroll = self.scroll.min(lines.len().saturating_sub(height));
+        let end = lines.len() - self.scroll;
+        let start = end.saturating_sub(height);
+
+        let visible = lines[start..end]
+            .iter()
+            .map(|line| Line::styled(line.clone(), line_style(line)))
+            .collect();
+
+        let mut title = format!("Logs ({} and above", self.level);
+        if !self.filter.is_empty() {
+            title.push_str(&format!(", matching \"{}\"", self.filter));
+        }
+        if self.scroll > 0 {
+            title.push_str(&format!(", {} lines up", self.scroll));
+        }
+        title.push(')');
+
+        (visible, title)
+    }
+}
+
+fn line_style(line: &str) -> Style {
+    if line.contains(" ERROR ") {
+        Style::default().fg(Color::Red)
+    } else if line.contains(" WARN ") {
+        Style::default().fg(Color::Yellow)
+    } else if line.contains(" DEBUG ") || line.contains(" TRACE ") {
+        Style::default().fg(Color::DarkGray)
+    } else {
+        Style::default()
+    }
+}
+
+#[cfg(test)]
+mod tui_tests {
+    use std::{rc::Weak, sync::Arc};
+
+    use configuration::app_config::AppConfig;
+    use ratatui::{
+        backend::TestBackend,
+        crossterm::event::{KeyCode, KeyEvent},
+        Terminal,
+    };
+    use tracing::Level;
+    use utils::logging::redaction::Redactor;
+
+    use super::{run, Dashboard};
+    use crate::{tasks::TaskRegistry, AppRuntime};
+
+    #[tokio::test]
+    async fn commands_using_the_terminal_are_refused() {
+        async fn error(command: &str) -> String {
+            let runtime = AppRuntime::new(Weak::new(), AppConfig::default());
+            let words = command.split_whitespace().map(String::from).collect();
+
+            run(&runtime, words).await.unwrap_err().to_string()
+        }
+
+        for command in [
+            "docs markdown",
+            "logs tail -f",
+            "completion bash",
+            "run-script -",
+        ] {
+            assert!(
+                error(command)
+                    .await
+                    .contains("can't be run inside the dashboard"),
+                "{command}"
+            );
+        }
+        // Anything else goes on to start the dashboard.
+        assert!(!error("logs tail").await.contains("inside the dashboard"));
+    }
+
+    #[test]
+    fn dashboard_shows_tasks_and_filtered_logs() {
+        let tasks = Arc::new(TaskRegistry::default());
+        let _running = tasks.track("long job", std::future::pending::<()>());
+
+        let mut dashboard = Dashboard::new(Weak::new(), tasks, Level::INFO);
+        dashboard.logs.push(" INFO rt: Copying files".to_string());
+        dashboard
+            .logs
+            .push(" WARN rt: Disk nearly full".to_string());
+
+        for key in [
+            KeyCode::Char('/'),
+            KeyCode::Char('d'),
+            KeyCode::Char('i'),
+            KeyCode::Char('s'),
+            KeyCode::Enter,
+            KeyCode::Char('4'),
+        ] {
+            assert!(dashboard.handle_key(KeyEvent::from(key)));
+        }
+        assert!(!dashboard.handle_key(KeyEvent::from(KeyCode::Char('q'))));
+
+        let mut config = AppConfig::default();
+        config.program.name = "ops@example.com".to_string();
+        let redactor = Redactor::new::<&str, _>(true, "***", &[], &[r"\S+@example\.com"]).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal
+            .draw(|frame| dashboard.draw(frame, &config, &redactor))
+            .unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+
+        assert!(screen.contains("Tasks (1 running)"));
+        assert!(screen.contains("long job"));
+        assert!(screen.contains("Disk nearly full"));
+        assert!(!screen.contains("Copying files"));
+        assert!(screen.contains("Logs (DEBUG and above, matching \"dis\")"));
+        assert!(screen.contains("\"cli_log_level\""));
+        assert!(screen.contains("\"name\": \"***\""));
+    }
+}
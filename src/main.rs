@@ -1,50 +1,88 @@
-use std::rc::{Rc, Weak};
-
-use cli::cli_match;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use cli::{cli_match, AppCommand, Cli, OutputFormat, ProfileFormatArg};
 use configuration::{app_config::AppConfig, AppConfigManager};
-use rt::AppRuntime;
+use metrics::server::MetricsServer;
+use rt::{
+    log_config::{self, CommandLineLogging},
+    shutdown::Shutdown,
+    AppRuntime,
+};
 use tracing::{debug, info, instrument, Level};
-use utils::{core_types::CoreResult, logging::LoggingManager, panic::initialize_panic_handler};
+use utils::{
+    core_types::{CoreError, CoreResult},
+    logging::{profiling::ProfileFormat, ring_buffer::dump_ring_buffer, LoggingManager},
+    panic::initialize_panic_handler,
+};
 
 async fn initialize_log_manager() -> LoggingManager {
-    let log_manager = LoggingManager::new();
+    let log_manager = LoggingManager::new().with_ring_buffer_logging(Level::TRACE);
 
     #[cfg(feature = "journald")]
     let log_manager = log_manager.with_journald_logging(Level::TRACE);
 
-    #[cfg(feature = "logfile")]
-    let log_manager = log_manager.with_logfile_logging(Level::TRACE);
+    // The logfile sink is only attached once the configuration says where it should go.
 
     log_manager.with_fmt_logging(Level::INFO)
 }
 
-async fn refresh_logging_with_config(
-    log_manager: LoggingManager, config: &AppConfig,
-) -> CoreResult<LoggingManager> {
-    let log_manager = log_manager.with_fmt_logging(config.logging.cli_log_level.parse::<Level>()?);
+fn enable_profiling_from_cli(log_manager: LoggingManager, cli: &Cli) -> LoggingManager {
+    let Some(path) = &cli.profile_trace else {
+        return log_manager;
+    };
 
-    #[cfg(feature = "journald")]
-    let log_manager =
-        log_manager.with_journald_logging(config.logging.journald_log_level.parse::<Level>()?);
+    let format = match cli.profile_format {
+        Some(ProfileFormatArg::Folded) => ProfileFormat::Folded,
+        Some(ProfileFormatArg::Chrome) => ProfileFormat::Chrome,
+        None => ProfileFormat::from_path(path),
+    };
 
-    #[cfg(feature = "logfile")]
-    let log_manager = log_manager
-        .with_logfile_logging(config.logging.rolling_log_level.parse::<Level>()?)
-        .with_logfile_prefix(config.logging.rolling_log_prefix.clone())
-        .with_logfile_base_path(config.logging.rolling_log_path.clone());
+    log_manager.with_profiling(path.clone(), format)
+}
 
-    log_manager.refresh()?;
+async fn start_metrics_server(config: &AppConfig) -> CoreResult<Option<MetricsServer>> {
+    if !config.metrics.server_enabled {
+        return Ok(None);
+    }
 
-    Ok(log_manager)
+    let address = config
+        .metrics
+        .server_address
+        .parse::<IpAddr>()
+        .map_err(|e| {
+            CoreError::MetricsConfigError(format!(
+                "Invalid server address \"{}\": {e}",
+                config.metrics.server_address
+            ))
+        })?;
+
+    let server = metrics::server::serve(
+        SocketAddr::new(address, config.metrics.server_port),
+        metrics::global(),
+    )
+    .await?;
+
+    info!("Serving metrics on http://{}/metrics", server.address);
+
+    Ok(Some(server))
 }
 
-#[instrument(skip(log_manager))]
-async fn entrypoint(log_manager: Weak<LoggingManager>) -> CoreResult<()> {
-    let command = cli_match()?;
-
+#[instrument(skip(log_manager, command, output_format, command_line_logging, shutdown))]
+async fn entrypoint(
+    log_manager: Weak<LoggingManager>, command: AppCommand, output_format: OutputFormat,
+    command_line_logging: CommandLineLogging, shutdown: Shutdown,
+) -> CoreResult<()> {
     let app_config = AppConfigManager::clone_to_app_config()?;
 
-    let app_state: AppRuntime = AppRuntime::new(log_manager, app_config);
+    let app_state: AppRuntime = AppRuntime::new(log_manager, app_config)
+        .with_output_format(output_format)
+        .with_command_line_logging(command_line_logging)
+        .with_shutdown(shutdown);
 
     app_state.enter(command).await?;
 
@@ -53,6 +91,9 @@ async fn entrypoint(log_manager: Weak<LoggingManager>) -> CoreResult<()> {
 
 #[tokio::main]
 async fn main() -> CoreResult<()> {
+    // Completion requests exit here, before anything else can write to stdout.
+    cli::complete_from_env();
+
     initialize_panic_handler()?;
 
     let mut log_manager = initialize_log_manager().await;
@@ -67,31 +108,73 @@ async fn main() -> CoreResult<()> {
         log_manager.get_syslog_identifier()
     );
 
+    // Parse the command line first so any config file it names is loaded before logging is
+    // reconfigured.
+    let cli = cli_match()?;
+
     let app_config: AppConfig = AppConfigManager::clone_to_app_config().unwrap();
 
     debug!("Configuration loaded.");
 
-    let log_manager = refresh_logging_with_config(log_manager, &app_config).await?;
+    let command_line_logging = CommandLineLogging {
+        args: cli.logging.clone(),
+        command: cli.command.to_string(),
+    };
+    let log_manager = enable_profiling_from_cli(log_manager, &cli);
+    let log_manager = log_config::configure(log_manager, &app_config, &command_line_logging)?;
+    log_manager.refresh()?;
+
+    // Held until exit, as dropping the server stops it.
+    let _metrics_server = start_metrics_server(&app_config).await?;
 
     // Initialise a shareable pointer to the log manager to maintain top-level ownership.
     // This prevents the log manager being dropped before the program has finished all logging.
     let log_manager_pointer = Rc::new(log_manager);
 
-    tokio::select! {
-        _ = tokio::signal::ctrl_c() => {
-            info!("Interrupted. Shutting down.");
-            Ok(())
-        },
-        res = entrypoint(Rc::downgrade(&log_manager_pointer)) => {
-            match res {
-                Ok(_) => {
-                    info!("Completed. Exiting.");
-                    Ok(())
-                },
-                Err(e) => {
-                    Err(e)
-                }
-            }
+    let shutdown = Shutdown::new(Duration::from_millis(app_config.shutdown.grace_period_ms));
+    shutdown.on_exit(log_manager_pointer.exit_flusher());
+    shutdown.listen_for_signals()?;
+
+    let result = shutdown
+        .run(entrypoint(
+            Rc::downgrade(&log_manager_pointer),
+            cli.command,
+            cli.output,
+            command_line_logging,
+            shutdown.clone(),
+        ))
+        .await;
+
+    match &result {
+        Ok(_) if shutdown.is_requested() => info!("Shut down. Exiting."),
+        Ok(_) => info!("Completed. Exiting."),
+        Err(_) => {}
+    }
+
+    // Report anything the rate limiter is still holding back before the final dump or exit.
+    log_manager_pointer.flush_rate_limits();
+
+    // Being interrupted isn't a crash, so there's nothing to dump for it.
+    if result.is_err() && !shutdown.is_requested() {
+        dump_ring_buffer();
+    }
+
+    let finished = log_manager_pointer.write_profile().and_then(|_| {
+        if !app_config.metrics.snapshot_path.is_empty() {
+            metrics::write_snapshot(Path::new(&app_config.metrics.snapshot_path))?;
         }
+
+        Ok(())
+    });
+
+    // Nothing is logged to the logfile after this, so it must come last.
+    log_manager_pointer.flush_background_sinks();
+
+    // Forced shutdowns exit from the signal handler. A command abandoned at the end of the
+    // grace period exits here, skipping waiting on anything it left running.
+    if shutdown.is_requested() && result.is_err() {
+        std::process::exit(shutdown.exit_code());
     }
+
+    result.and(finished)
 }
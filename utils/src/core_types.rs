@@ -18,6 +18,22 @@ pub enum CoreError {
     ErrorHandlerInitialisationError(#[from] color_eyre::eyre::InstallError),
     #[error("App configuration Error")]
     AppConfigError(#[from] config::ConfigError),
+    #[error("Logging configuration Error: {0}")]
+    LoggingConfigError(String),
+    #[error("Metrics configuration Error: {0}")]
+    MetricsConfigError(String),
+    #[error("Supervisor configuration Error: {0}")]
+    SupervisorConfigError(String),
+    #[error("Worker pool Error: {0}")]
+    WorkerPoolError(String),
+    #[error("Command alias Error: {0}")]
+    AliasError(String),
+    #[error("Output rendering Error: {0}")]
+    OutputError(String),
+    #[error("Log redaction pattern Error")]
+    RedactionPatternError(#[from] regex::Error),
+    #[error("Interrupted: {0}")]
+    Interrupted(String),
     #[error("Tokio Error: {0}")]
     GeneralTokioError(String),
 }
@@ -2,3 +2,4 @@ pub mod core_types;
 pub mod logging;
 pub mod panic;
 pub mod project_info;
+pub mod random;
@@ -1,53 +1,99 @@
-use std::collections::HashMap;
-
-use tracing::{level_filters::LevelFilter, Level};
+use std::{collections::HashMap, io::IsTerminal, path::PathBuf, str::FromStr, sync::Arc};
+
+use format::LogFormat;
+use parking_lot::RwLock;
+use profiling::{ProfileFormat, Profiler};
+use rate_limit::{RateLimitConfig, RateLimitLayer, RateLimiter};
+use redaction::{RedactingLayer, Redactor};
+use ring_buffer::{RingBuffer, RingBufferMakeWriter, DEFAULT_RING_BUFFER_CAPACITY};
+use sinks::{BaseLayer, SinkSet};
+use tracing::{Dispatch, Level};
 use tracing_error::ErrorLayer;
 use tracing_subscriber::{
-    filter::{self, Filtered},
-    fmt::{self},
+    field::RecordFields,
+    filter::{LevelFilter, Targets},
+    fmt::{
+        format::{DefaultFields, Writer},
+        FormatFields,
+    },
     prelude::*,
-    reload::{self, Handle, Layer},
-    Registry,
 };
 
 use crate::core_types::{CoreError, CoreResult};
 
-type BaseLayer = Box<dyn tracing_subscriber::Layer<Registry> + Send + Sync>;
-type ReloadLayer = Layer<Filtered<BaseLayer, LevelFilter, Registry>, Registry>;
-type ReloadHandle = Handle<Filtered<BaseLayer, LevelFilter, Registry>, Registry>;
+pub mod file_naming;
+pub mod format;
+pub mod profiling;
+pub mod rate_limit;
+pub mod redaction;
+pub mod ring_buffer;
+pub mod sinks;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub const FMT_SINK: &str = "fmt";
+pub const RING_BUFFER_SINK: &str = "ring_buffer";
+pub const PROFILE_SINK: &str = "profile";
+#[cfg(feature = "journald")]
+pub const JOURNALD_SINK: &str = "journald";
+#[cfg(feature = "logfile")]
+pub const LOGFILE_SINK: &str = "logfile";
+#[cfg(any(test, feature = "testing"))]
+pub const CAPTURE_SINK: &str = "capture";
+
+/// Field formatter for sinks without ANSI colours.
+/// Span fields are formatted once per formatter type and shared between sinks, so a distinct
+/// type keeps plain sinks from reusing the coloured fields written for the console.
+#[derive(Debug, Default)]
+pub struct PlainFields<F = DefaultFields>(F);
+
+impl<'writer, F> FormatFields<'writer> for PlainFields<F>
+where
+    F: FormatFields<'writer>,
+{
+    fn format_fields<R: RecordFields>(
+        &self, writer: Writer<'writer>, fields: R,
+    ) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
 
+#[derive(Debug)]
 pub struct LogLayerConfig {
     pub log_level: Level,
+    /// Per-target levels overriding `log_level`, such as `rt=trace`.
+    pub directives: Targets,
+    /// Only used by text sinks. Sinks such as journald have a format of their own.
+    pub format: LogFormat,
     pub params: HashMap<String, String>,
-    pub reload_handle: Option<ReloadHandle>,
-}
-
-impl std::fmt::Debug for LogLayerConfig {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("LogLayerConfig")
-            .field("log_level", &self.log_level)
-            .field("params", &self.params)
-            .finish_non_exhaustive()
-    }
 }
 
 impl LogLayerConfig {
     fn new(level: Level) -> Self {
         Self {
             log_level: level,
+            directives: Targets::new(),
+            format: LogFormat::default(),
             params: HashMap::new(),
-            reload_handle: None,
         }
     }
+
+    fn filter(&self) -> Targets {
+        self.directives.clone().with_default(self.log_level)
+    }
 }
 
+#[cfg(feature = "logfile")]
+pub const DEFAULT_MAX_LOG_FILES: usize = 5;
+
 #[cfg(feature = "logfile")]
 #[derive(Debug)]
 pub struct LogFileLogLayerConfig {
     pub layer_config: LogLayerConfig,
     pub base_dir: String,
     pub prefix: String,
-    pub _guard: Option<tracing_appender::non_blocking::WorkerGuard>,
+    pub naming: file_naming::LogFileNaming,
+    pub max_files: usize,
 }
 
 #[cfg(feature = "logfile")]
@@ -57,18 +103,112 @@ impl LogFileLogLayerConfig {
             layer_config: LogLayerConfig::new(level),
             base_dir: "logs/".to_string(),
             prefix: "rust-starter-template".to_string(),
-            _guard: None,
+            naming: file_naming::LogFileNaming::default(),
+            max_files: DEFAULT_MAX_LOG_FILES,
+        }
+    }
+
+    /// The settings which require the file appender to be rebuilt when changed.
+    fn signature(&self) -> String {
+        format!(
+            "{}|{}|{}|{}|{}",
+            self.base_dir,
+            self.prefix,
+            self.naming.signature(),
+            self.max_files,
+            self.layer_config.format.signature()
+        )
+    }
+}
+
+/// Where console logs are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConsoleWriter {
+    /// Keeps logs apart from command output on stdout.
+    #[default]
+    Stderr,
+    Stdout,
+}
+
+impl FromStr for ConsoleWriter {
+    type Err = CoreError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "stderr" => Ok(Self::Stderr),
+            "stdout" => Ok(Self::Stdout),
+            _ => Err(CoreError::LoggingConfigError(format!(
+                "Unknown console writer \"{value}\". Expected stderr or stdout."
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConsoleLogLayerConfig {
+    pub layer_config: LogLayerConfig,
+    pub writer: ConsoleWriter,
+}
+
+impl ConsoleLogLayerConfig {
+    fn new(level: Level) -> Self {
+        Self {
+            layer_config: LogLayerConfig::new(level),
+            writer: ConsoleWriter::default(),
+        }
+    }
+
+    fn signature(&self) -> String {
+        format!("{:?}|{}", self.writer, self.layer_config.format.signature())
+    }
+
+    fn layer(&self) -> BaseLayer {
+        let format = &self.layer_config.format;
+
+        match self.writer {
+            ConsoleWriter::Stderr => format.layer(std::io::stderr, std::io::stderr().is_terminal()),
+            ConsoleWriter::Stdout => format.layer(std::io::stdout, std::io::stdout().is_terminal()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RingBufferLogLayerConfig {
+    pub layer_config: LogLayerConfig,
+    pub capacity: usize,
+    pub dump_path: Option<PathBuf>,
+}
+
+impl RingBufferLogLayerConfig {
+    fn new(level: Level) -> Self {
+        Self {
+            layer_config: LogLayerConfig::new(level),
+            capacity: DEFAULT_RING_BUFFER_CAPACITY,
+            dump_path: None,
         }
     }
 }
 
+/// The configured sinks, applied to the live `SinkSet` by `refresh`.
 #[derive(Default, Debug)]
-pub struct LoggingManager {
-    fmt: Option<LogLayerConfig>,
+struct SinkSettings {
+    fmt: Option<ConsoleLogLayerConfig>,
+    ring_buffer: Option<RingBufferLogLayerConfig>,
     #[cfg(feature = "journald")]
     journald: Option<LogLayerConfig>,
     #[cfg(feature = "logfile")]
     logfile: Option<LogFileLogLayerConfig>,
+    #[cfg(any(test, feature = "testing"))]
+    capture: Option<testing::CaptureLogLayerConfig>,
+}
+
+#[derive(Default, Debug)]
+pub struct LoggingManager {
+    redactor: Arc<RwLock<Redactor>>,
+    rate_limiter: Arc<RateLimiter>,
+    sinks: SinkSet,
+    settings: RwLock<SinkSettings>,
+    profiler: Option<Profiler>,
 }
 
 impl LoggingManager {
@@ -77,40 +217,162 @@ impl LoggingManager {
     }
 
     pub fn with_fmt_logging(mut self, level: Level) -> Self {
-        if let Some(ref mut fmt) = &mut self.fmt {
-            fmt.log_level = level;
+        if let Some(ref mut fmt) = &mut self.settings.get_mut().fmt {
+            fmt.layer_config.log_level = level;
+        } else {
+            self.settings.get_mut().fmt = Some(ConsoleLogLayerConfig::new(level));
+        }
+
+        self
+    }
+
+    /// Override the console level for particular targets.
+    pub fn with_fmt_directives(mut self, directives: Targets) -> Self {
+        let fmt = self.settings.get_mut().fmt.as_mut().unwrap();
+        fmt.layer_config.directives = directives;
+
+        self
+    }
+
+    /// Stop logging to the console. Takes effect on the next `refresh`.
+    pub fn without_fmt_logging(mut self) -> Self {
+        self.settings.get_mut().fmt = None;
+
+        self
+    }
+
+    pub fn with_fmt_format(mut self, format: LogFormat) -> Self {
+        let fmt = self.settings.get_mut().fmt.as_mut().unwrap();
+        fmt.layer_config.format = format;
+
+        self
+    }
+
+    pub fn with_fmt_writer(mut self, writer: ConsoleWriter) -> Self {
+        let fmt = self.settings.get_mut().fmt.as_mut().unwrap();
+        fmt.writer = writer;
+
+        self
+    }
+
+    /// Replace the rules used to mask sensitive values across every sink.
+    /// Takes effect immediately, even after `build`.
+    pub fn with_redaction(self, redactor: Redactor) -> Self {
+        *self.redactor.write() = redactor;
+
+        self
+    }
+
+    /// Replace the sampling and rate limiting settings applied before events reach any sink.
+    /// Takes effect immediately, even after `build`.
+    pub fn with_rate_limit(self, config: RateLimitConfig) -> Self {
+        self.rate_limiter.set_config(config);
+
+        self
+    }
+
+    /// Report any events still being suppressed by the rate limiter.
+    pub fn flush_rate_limits(&self) {
+        self.rate_limiter.flush();
+    }
+
+    /// Keep the most recent events in memory so they can be dumped on a crash.
+    pub fn with_ring_buffer_logging(mut self, level: Level) -> Self {
+        if let Some(ref mut ring_buffer) = &mut self.settings.get_mut().ring_buffer {
+            ring_buffer.layer_config.log_level = level;
         } else {
-            self.fmt = Some(LogLayerConfig::new(level));
+            self.settings.get_mut().ring_buffer = Some(RingBufferLogLayerConfig::new(level));
         }
 
         self
     }
 
+    /// Stop keeping recent events in memory. Takes effect on the next `refresh`.
+    pub fn without_ring_buffer_logging(mut self) -> Self {
+        self.settings.get_mut().ring_buffer = None;
+
+        self
+    }
+
+    pub fn with_ring_buffer_capacity(mut self, capacity: usize) -> Self {
+        let ring_buffer = self.settings.get_mut().ring_buffer.as_mut().unwrap();
+        ring_buffer.capacity = capacity;
+
+        self
+    }
+
+    pub fn with_ring_buffer_format(mut self, format: LogFormat) -> Self {
+        let ring_buffer = self.settings.get_mut().ring_buffer.as_mut().unwrap();
+        ring_buffer.layer_config.format = format;
+
+        self
+    }
+
+    /// Set the file the ring buffer is dumped to. An empty path dumps to stderr.
+    pub fn with_ring_buffer_dump_path(mut self, dump_path: String) -> Self {
+        let ring_buffer = self.settings.get_mut().ring_buffer.as_mut().unwrap();
+        ring_buffer.dump_path = (!dump_path.is_empty()).then(|| PathBuf::from(dump_path));
+
+        self
+    }
+
+    /// Record span timings to `path`, written out by `write_profile`.
+    pub fn with_profiling(mut self, path: PathBuf, format: ProfileFormat) -> Self {
+        self.profiler = Some(Profiler::new(path, format));
+
+        self
+    }
+
+    /// Write the span timings recorded so far, if profiling is enabled.
+    pub fn write_profile(&self) -> CoreResult<()> {
+        if let Some(profiler) = &self.profiler {
+            profiler.write()?;
+        }
+
+        Ok(())
+    }
+
     #[cfg(feature = "journald")]
     pub fn with_journald_logging(mut self, level: Level) -> Self {
-        if let Some(ref mut journald) = &mut self.journald {
+        if let Some(ref mut journald) = &mut self.settings.get_mut().journald {
             journald.log_level = level;
         } else {
-            self.journald = Some(LogLayerConfig::new(level));
+            self.settings.get_mut().journald = Some(LogLayerConfig::new(level));
         }
 
         self
     }
 
+    /// Stop logging to journald. Takes effect on the next `refresh`.
+    #[cfg(feature = "journald")]
+    pub fn without_journald_logging(mut self) -> Self {
+        self.settings.get_mut().journald = None;
+
+        self
+    }
+
     #[cfg(feature = "logfile")]
     pub fn with_logfile_logging(mut self, level: Level) -> Self {
-        if let Some(ref mut logfile) = &mut self.logfile {
+        if let Some(ref mut logfile) = &mut self.settings.get_mut().logfile {
             logfile.layer_config.log_level = level;
         } else {
-            self.logfile = Some(LogFileLogLayerConfig::new(level));
+            self.settings.get_mut().logfile = Some(LogFileLogLayerConfig::new(level));
         }
 
         self
     }
 
+    /// Stop logging to rolling log files. Takes effect on the next `refresh`.
+    #[cfg(feature = "logfile")]
+    pub fn without_logfile_logging(mut self) -> Self {
+        self.settings.get_mut().logfile = None;
+
+        self
+    }
+
     #[cfg(feature = "logfile")]
     pub fn with_logfile_base_path(mut self, base_dir: String) -> Self {
-        let logfile = self.logfile.as_mut().unwrap();
+        let logfile = self.settings.get_mut().logfile.as_mut().unwrap();
         logfile.base_dir = base_dir;
 
         self
@@ -118,15 +380,47 @@ impl LoggingManager {
 
     #[cfg(feature = "logfile")]
     pub fn with_logfile_prefix(mut self, prefix: String) -> Self {
-        let logfile = self.logfile.as_mut().unwrap();
+        let logfile = self.settings.get_mut().logfile.as_mut().unwrap();
         logfile.prefix = prefix;
 
         self
     }
 
+    #[cfg(feature = "logfile")]
+    pub fn with_logfile_naming(mut self, naming: file_naming::LogFileNaming) -> Self {
+        let logfile = self.settings.get_mut().logfile.as_mut().unwrap();
+        logfile.naming = naming;
+
+        self
+    }
+
+    #[cfg(feature = "logfile")]
+    pub fn with_logfile_max_files(mut self, max_files: usize) -> Self {
+        let logfile = self.settings.get_mut().logfile.as_mut().unwrap();
+        logfile.max_files = max_files;
+
+        self
+    }
+
+    #[cfg(feature = "logfile")]
+    pub fn with_logfile_format(mut self, format: LogFormat) -> Self {
+        let logfile = self.settings.get_mut().logfile.as_mut().unwrap();
+        logfile.layer_config.format = format;
+
+        self
+    }
+
+    /// Record events into `capture` so tests can assert on them.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_capture_logging(mut self, level: Level, capture: testing::LogCapture) -> Self {
+        self.settings.get_mut().capture = Some(testing::CaptureLogLayerConfig::new(level, capture));
+
+        self
+    }
+
     #[cfg(feature = "journald")]
     pub fn get_syslog_identifier(&self) -> String {
-        if let Some(journald) = &self.journald {
+        if let Some(journald) = &self.settings.read().journald {
             journald
                 .params
                 .get("syslog_identifier")
@@ -137,113 +431,240 @@ impl LoggingManager {
         }
     }
 
-    /// Set the global logging filters.
-    /// Can only be called once.
-    pub fn build(&mut self) -> CoreResult<()> {
-        let registry = tracing_subscriber::registry();
-
-        let mut layers = Vec::new();
-        if let Some(ref mut fmt_config) = self.fmt {
-            let (layer, reload_handle): (ReloadLayer, ReloadHandle) = reload::Layer::new(
-                fmt::Layer::new()
-                    .boxed()
-                    .with_filter(filter::LevelFilter::from_level(fmt_config.log_level)),
-            );
-            fmt_config.reload_handle = Some(reload_handle);
-            layers.push(layer);
-        } else {
-            panic!("Cannot initialise logging without fmt_params.");
-        }
+    /// Attach a sink by hand, replacing any existing sink of the same name.
+    /// Unlike configured sinks, it stays attached across refreshes until detached.
+    pub fn attach_sink(&self, name: &str, level: Level, layer: BaseLayer) {
+        self.sinks.attach(name, level, layer);
+    }
 
-        #[cfg(feature = "journald")]
-        if let Some(ref mut journald_config) = self.journald {
-            let journald_layer = tracing_journald::layer().map_err(CoreError::from)?;
+    /// Detach a sink immediately, returning whether it was attached.
+    /// Configured sinks are reattached on the next `refresh` unless disabled first.
+    pub fn detach_sink(&self, name: &str) -> bool {
+        self.sinks.detach(name)
+    }
 
-            let syslog_identifier = journald_layer.syslog_identifier();
+    /// Change the level of an attached sink immediately, returning whether it was attached.
+    pub fn set_sink_level(&self, name: &str, level: Level) -> bool {
+        self.sinks.set_level(name, level)
+    }
 
-            journald_config.params.insert(
-                "syslog_identifier".to_string(),
-                syslog_identifier.to_string(),
-            );
+    /// Replace the whole filter of an attached sink immediately, returning whether it was
+    /// attached. An empty `Targets` silences the sink.
+    pub fn set_sink_filter(&self, name: &str, filter: Targets) -> bool {
+        self.sinks.set_filter(name, filter)
+    }
 
-            let (layer, reload_handle): (ReloadLayer, ReloadHandle) = reload::Layer::new(
-                journald_layer
-                    .boxed()
-                    .with_filter(filter::LevelFilter::from_level(journald_config.log_level)),
-            );
+    /// The filter of an attached sink, with its per-target directives.
+    pub fn sink_filter(&self, name: &str) -> Option<Targets> {
+        self.sinks.filter(name)
+    }
 
-            journald_config.reload_handle = Some(reload_handle);
-            layers.push(layer);
+    /// The default level of an attached sink, without its per-target directives.
+    pub fn sink_level(&self, name: &str) -> Option<LevelFilter> {
+        self.sinks.level(name)
+    }
+
+    pub fn sink_names(&self) -> Vec<String> {
+        self.sinks.names()
+    }
+
+    /// Flush and detach sinks that write in the background, such as the logfile, so
+    /// nothing they've buffered is lost on exit. Call just before exiting, as events logged
+    /// afterwards only reach the remaining sinks.
+    pub fn flush_background_sinks(&self) {
+        self.sinks.detach_guarded();
+    }
+
+    /// Does what `flush_rate_limits` and `flush_background_sinks` do, from any thread, for
+    /// exiting from somewhere without the manager such as a signal handler.
+    pub fn exit_flusher(&self) -> impl Fn() + Send + Sync + 'static {
+        let rate_limiter = self.rate_limiter.clone();
+        let sinks = self.sinks.clone();
+
+        move || {
+            rate_limiter.flush();
+            sinks.detach_guarded();
         }
+    }
 
-        #[cfg(feature = "logfile")]
-        if let Some(ref mut logfile_config) = self.logfile {
-            let file_appender = tracing_appender::rolling::RollingFileAppender::builder()
-                .rotation(tracing_appender::rolling::Rotation::DAILY)
-                .filename_prefix(&logfile_config.prefix)
-                .filename_suffix("log")
-                .max_log_files(5)
-                .build(&logfile_config.base_dir)
-                .map_err(CoreError::from)?;
+    fn subscriber(&self) -> impl tracing::Subscriber + Send + Sync {
+        tracing_subscriber::registry()
+            .with(RedactingLayer::new(
+                self.sinks.layer().and_then(ErrorLayer::default()),
+                self.redactor.clone(),
+            ))
+            .with(RateLimitLayer::new(self.rate_limiter.clone()))
+    }
 
-            let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+    /// Set the global logging filters.
+    /// Can only be called once.
+    pub fn build(&mut self) -> CoreResult<()> {
+        let dispatch = Dispatch::new(self.subscriber());
+        self.rate_limiter.start_ticker(&dispatch);
+        dispatch.init();
 
-            let fmt_inner_layer = fmt::Layer::new()
-                .with_ansi(false)
-                .with_writer(non_blocking)
-                .boxed()
-                .with_filter(filter::LevelFilter::from_level(
-                    logfile_config.layer_config.log_level,
-                ));
-            let (layer, reload_handle): (ReloadLayer, ReloadHandle) =
-                reload::Layer::new(fmt_inner_layer);
+        self.refresh()
+    }
 
-            logfile_config._guard = Some(_guard);
-            logfile_config.layer_config.reload_handle = Some(reload_handle);
+    /// Set the logging filters for the current thread only, until the returned guard is dropped.
+    /// Unlike `build`, this can be called any number of times, which makes it suitable for tests.
+    pub fn build_scoped(&mut self) -> CoreResult<tracing::subscriber::DefaultGuard> {
+        let dispatch = Dispatch::new(self.subscriber());
+        self.rate_limiter.start_ticker(&dispatch);
+        let guard = dispatch.set_default();
 
-            layers.push(layer);
-        }
+        self.refresh()?;
 
-        let error_layer = ErrorLayer::default();
+        Ok(guard)
+    }
 
-        registry.with(layers).with(error_layer).init();
+    /// Take on the sink, redaction and rate limiting settings of `configured`, a manager set
+    /// up with the builders but never built, and refresh to apply them. Profiling and sinks
+    /// attached by hand are kept. Unlike the builders, this works on a shared manager.
+    pub fn reconfigure(&self, configured: LoggingManager) -> CoreResult<()> {
+        *self.settings.write() = configured.settings.into_inner();
+        *self.redactor.write() = configured.redactor.read().clone();
+        self.rate_limiter
+            .set_config(configured.rate_limiter.config());
 
-        Ok(())
+        self.refresh()
     }
 
-    /// Refresh the global subscribers with any updated filters.
+    /// Bring the live sinks in line with the configuration.
+    /// Sinks are attached, detached, or rebuilt as needed and have their levels updated.
     pub fn refresh(&self) -> CoreResult<()> {
-        if let Some(fmt_config) = &self.fmt {
-            let reload_handle = fmt_config.reload_handle.as_ref().unwrap();
-            reload_handle
-                .modify(|layer_box| {
-                    *layer_box.filter_mut() = filter::LevelFilter::from_level(fmt_config.log_level)
-                })
-                .map_err(CoreError::from)?;
+        let mut configured = Vec::new();
+        let settings = &mut *self.settings.write();
+
+        if let Some(fmt_config) = &settings.fmt {
+            let signature = fmt_config.signature();
+
+            if self.sinks.needs_rebuild(FMT_SINK, &signature) {
+                self.sinks.attach_managed(
+                    FMT_SINK,
+                    fmt_config.layer_config.filter(),
+                    signature,
+                    fmt_config.layer(),
+                    None,
+                );
+            }
+            self.sinks
+                .set_filter(FMT_SINK, fmt_config.layer_config.filter());
+            configured.push(FMT_SINK);
+        }
+
+        if let Some(ring_buffer_config) = &settings.ring_buffer {
+            let buffer = RingBuffer::global();
+            buffer.set_capacity(ring_buffer_config.capacity);
+            buffer.set_dump_path(ring_buffer_config.dump_path.clone());
+
+            let signature = ring_buffer_config.layer_config.format.signature();
+
+            if self.sinks.needs_rebuild(RING_BUFFER_SINK, &signature) {
+                self.sinks.attach_managed(
+                    RING_BUFFER_SINK,
+                    ring_buffer_config.layer_config.filter(),
+                    signature,
+                    ring_buffer_config
+                        .layer_config
+                        .format
+                        .layer(RingBufferMakeWriter::new(buffer), false),
+                    None,
+                );
+            }
+            self.sinks
+                .set_filter(RING_BUFFER_SINK, ring_buffer_config.layer_config.filter());
+            configured.push(RING_BUFFER_SINK);
+        }
+
+        if let Some(profiler) = &self.profiler {
+            let signature = profiler.path().display().to_string();
+
+            if self.sinks.needs_rebuild(PROFILE_SINK, &signature) {
+                self.sinks.attach_managed(
+                    PROFILE_SINK,
+                    Targets::new().with_default(Level::TRACE),
+                    signature,
+                    profiler.layer().boxed(),
+                    None,
+                );
+            }
+            configured.push(PROFILE_SINK);
         }
 
         #[cfg(feature = "journald")]
-        if let Some(journald_config) = &self.journald {
-            let reload_handle = journald_config.reload_handle.as_ref().unwrap();
-            reload_handle
-                .modify(|layer_box| {
-                    *layer_box.filter_mut() =
-                        filter::LevelFilter::from_level(journald_config.log_level)
-                })
-                .map_err(CoreError::from)?;
+        if let Some(ref mut journald_config) = settings.journald {
+            if self.sinks.needs_rebuild(JOURNALD_SINK, "") {
+                let journald_layer = tracing_journald::layer().map_err(CoreError::from)?;
+
+                let syslog_identifier = journald_layer.syslog_identifier();
+
+                journald_config.params.insert(
+                    "syslog_identifier".to_string(),
+                    syslog_identifier.to_string(),
+                );
+
+                self.sinks.attach_managed(
+                    JOURNALD_SINK,
+                    journald_config.filter(),
+                    String::new(),
+                    journald_layer.boxed(),
+                    None,
+                );
+            }
+            self.sinks
+                .set_filter(JOURNALD_SINK, journald_config.filter());
+            configured.push(JOURNALD_SINK);
         }
 
         #[cfg(feature = "logfile")]
-        if let Some(logfile_config) = &self.logfile {
-            let reload_handle = logfile_config.layer_config.reload_handle.as_ref().unwrap();
-            reload_handle
-                .modify(|layer_box| {
-                    *layer_box.filter_mut() =
-                        filter::LevelFilter::from_level(logfile_config.layer_config.log_level)
-                })
-                .map_err(CoreError::from)?;
+        if let Some(logfile_config) = &settings.logfile {
+            let signature = logfile_config.signature();
+
+            if self.sinks.needs_rebuild(LOGFILE_SINK, &signature) {
+                let file_appender = file_naming::build_appender(
+                    &logfile_config.base_dir,
+                    &logfile_config.prefix,
+                    &logfile_config.naming,
+                    logfile_config.max_files,
+                )?;
+
+                let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+
+                self.sinks.attach_managed(
+                    LOGFILE_SINK,
+                    logfile_config.layer_config.filter(),
+                    signature,
+                    logfile_config
+                        .layer_config
+                        .format
+                        .layer(non_blocking, false),
+                    Some(Box::new(guard)),
+                );
+            }
+            self.sinks
+                .set_filter(LOGFILE_SINK, logfile_config.layer_config.filter());
+            configured.push(LOGFILE_SINK);
         }
 
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(capture_config) = &settings.capture {
+            if self.sinks.needs_rebuild(CAPTURE_SINK, "") {
+                self.sinks.attach_managed(
+                    CAPTURE_SINK,
+                    capture_config.layer_config.filter(),
+                    String::new(),
+                    capture_config.capture.layer().boxed(),
+                    None,
+                );
+            }
+            self.sinks
+                .set_filter(CAPTURE_SINK, capture_config.layer_config.filter());
+            configured.push(CAPTURE_SINK);
+        }
+
+        self.sinks.retain_managed(&configured);
+
         Ok(())
     }
 }
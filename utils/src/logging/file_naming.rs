@@ -0,0 +1,176 @@
+#[cfg(feature = "logfile")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "logfile")]
+use chrono::Utc;
+#[cfg(feature = "logfile")]
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+#[cfg(feature = "logfile")]
+use crate::core_types::{CoreError, CoreResult};
+
+pub const LOG_FILE_SUFFIX: &str = "log";
+/// Points at the file of the most recent invocation when logging per invocation.
+pub const LATEST_LOG_LINK: &str = "latest.log";
+
+/// How log files are named, and so how often a new one is started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LogFileNaming {
+    /// A new file each day, named `<prefix>.<YYYY-MM-DD>.log`.
+    #[default]
+    Daily,
+    /// A new file each run, named `<prefix>.<timestamp>.<command>.<pid>.log`, with
+    /// `latest.log` linking to it.
+    PerInvocation { command: String },
+}
+
+impl LogFileNaming {
+    /// Name each run's file after `command`, as given by the command's `Display`.
+    pub fn per_invocation(command: &str) -> Self {
+        let command = command
+            .split(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<&str>>()
+            .join("-");
+
+        Self::PerInvocation { command }
+    }
+
+    pub fn signature(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+/// Drop a trailing `.log` from a configured prefix, which would otherwise be doubled up by
+/// the suffix the appender adds.
+pub fn normalize_prefix(prefix: &str) -> &str {
+    prefix
+        .strip_suffix(LOG_FILE_SUFFIX)
+        .and_then(|prefix| prefix.strip_suffix('.'))
+        .unwrap_or(prefix)
+}
+
+/// Build an appender writing to `base_dir` according to `naming`, keeping at most
+/// `max_files` files with the given prefix.
+#[cfg(feature = "logfile")]
+pub fn build_appender(
+    base_dir: &str, prefix: &str, naming: &LogFileNaming, max_files: usize,
+) -> CoreResult<RollingFileAppender> {
+    let prefix = normalize_prefix(prefix);
+
+    match naming {
+        LogFileNaming::Daily => RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(prefix)
+            .filename_suffix(LOG_FILE_SUFFIX)
+            .max_log_files(max_files)
+            .build(base_dir)
+            .map_err(|e| CoreError::from(e).into()),
+        LogFileNaming::PerInvocation { command } => {
+            // The link target is stored as given, so it needs an absolute path to resolve
+            // from inside the log directory.
+            std::fs::create_dir_all(base_dir).map_err(CoreError::from)?;
+            let base_dir = std::fs::canonicalize(base_dir).map_err(CoreError::from)?;
+
+            // Leave room for the file about to be created.
+            prune_log_files(&base_dir, prefix, max_files.saturating_sub(1))?;
+
+            let file_prefix = format!(
+                "{prefix}.{}.{command}.{}",
+                Utc::now().format("%Y-%m-%dT%H-%M-%SZ"),
+                std::process::id()
+            );
+
+            RollingFileAppender::builder()
+                .rotation(Rotation::NEVER)
+                .filename_prefix(file_prefix)
+                .filename_suffix(LOG_FILE_SUFFIX)
+                .latest_symlink(LATEST_LOG_LINK)
+                .build(&base_dir)
+                .map_err(|e| CoreError::from(e).into())
+        }
+    }
+}
+
+/// Delete the oldest log files with `prefix` until at most `keep` remain.
+/// Names start with a sortable date, so sorting by name sorts by age.
+#[cfg(feature = "logfile")]
+fn prune_log_files(base_dir: &Path, prefix: &str, keep: usize) -> CoreResult<()> {
+    let file_prefix = format!("{prefix}.");
+    let file_suffix = format!(".{LOG_FILE_SUFFIX}");
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(base_dir)
+        .map_err(CoreError::from)?
+        .filter_map(Result::ok)
+        // Directory entries don't follow symlinks, so `latest.log` is never counted.
+        .filter(|entry| entry.metadata().is_ok_and(|metadata| metadata.is_file()))
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(&file_prefix) && name.ends_with(&file_suffix))
+        })
+        .map(|entry| entry.path())
+        .collect();
+
+    if files.len() <= keep {
+        return Ok(());
+    }
+
+    files.sort();
+    for file in &files[..files.len() - keep] {
+        std::fs::remove_file(file).map_err(CoreError::from)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod file_naming_tests {
+    use super::{normalize_prefix, LogFileNaming};
+
+    #[test]
+    fn prefixes_and_commands_are_made_file_safe() {
+        assert_eq!("app", normalize_prefix("app.log"));
+        assert_eq!("app", normalize_prefix("app"));
+        assert_eq!(
+            LogFileNaming::PerInvocation {
+                command: "TasksDemo-64".to_string()
+            },
+            LogFileNaming::per_invocation("TasksDemo(64)")
+        );
+    }
+
+    #[cfg(feature = "logfile")]
+    #[test]
+    fn per_invocation_files_are_linked_and_pruned() {
+        use super::{build_appender, LATEST_LOG_LINK};
+
+        let dir = std::env::temp_dir().join(format!("file-naming-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for old in [
+            "app.2024-01-01T00-00-00Z.a.1.log",
+            "app.2024-01-02T00-00-00Z.a.2.log",
+        ] {
+            std::fs::write(dir.join(old), "").unwrap();
+        }
+
+        let naming = LogFileNaming::per_invocation("Run");
+        let appender = build_appender(dir.to_str().unwrap(), "app.log", &naming, 2);
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        let linked = std::fs::read_link(dir.join(LATEST_LOG_LINK)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(appender.is_ok());
+        assert_eq!(3, names.len());
+        assert_eq!("app.2024-01-02T00-00-00Z.a.2.log", names[0]);
+        assert!(names[1].ends_with(&format!(".Run.{}.log", std::process::id())));
+        assert_eq!(LATEST_LOG_LINK, names[2]);
+        assert!(linked.ends_with(&names[1]));
+    }
+}
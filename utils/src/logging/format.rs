@@ -0,0 +1,375 @@
+use std::str::FromStr;
+
+use tracing::{span, Event};
+use tracing_subscriber::{
+    fmt::{
+        self,
+        format::{DefaultFields, FmtSpan, Format, JsonFields, Pretty},
+        time::{ChronoLocal, SystemTime, Uptime},
+        FormatEvent, FormatFields, FormattedFields, MakeWriter,
+    },
+    layer::Context,
+    registry::SpanRef,
+    Layer, Registry,
+};
+
+use super::{sinks::BaseLayer, PlainFields};
+use crate::core_types::CoreError;
+
+/// How each event's timestamp is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// RFC 3339 wall-clock time in UTC.
+    #[default]
+    Utc,
+    /// RFC 3339 wall-clock time in the local timezone.
+    Local,
+    /// Time elapsed since the sink was built.
+    Uptime,
+    None,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = CoreError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "utc" => Ok(Self::Utc),
+            "local" => Ok(Self::Local),
+            "uptime" => Ok(Self::Uptime),
+            "none" => Ok(Self::None),
+            _ => Err(CoreError::LoggingConfigError(format!(
+                "Unknown timestamp format \"{value}\". Expected utc, local, uptime or none."
+            ))),
+        }
+    }
+}
+
+/// The overall layout of each event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogStyle {
+    /// One line per event, with the fields of every span in scope.
+    #[default]
+    Full,
+    /// One line per event, with span names but only the event's own fields.
+    Compact,
+    /// Several lines per event, for reading by eye.
+    Pretty,
+    /// One JSON object per event, for other tools to read.
+    Json,
+}
+
+impl FromStr for LogStyle {
+    type Err = CoreError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "full" => Ok(Self::Full),
+            "compact" => Ok(Self::Compact),
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            _ => Err(CoreError::LoggingConfigError(format!(
+                "Unknown log style \"{value}\". Expected full, compact, pretty or json."
+            ))),
+        }
+    }
+}
+
+/// Whether a sink writes ANSI colour codes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Colour only when writing to a terminal and `NO_COLOR` is unset.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorMode {
+    type Err = CoreError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(CoreError::LoggingConfigError(format!(
+                "Unknown color mode \"{value}\". Expected auto, always or never."
+            ))),
+        }
+    }
+}
+
+impl ColorMode {
+    /// Resolve the mode for a sink, given whether its writer is a terminal.
+    pub fn use_ansi(self, is_terminal: bool) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => {
+                is_terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+}
+
+/// How a text sink lays out each event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFormat {
+    pub style: LogStyle,
+    pub timestamp: TimestampFormat,
+    pub target: bool,
+    pub file_line: bool,
+    pub thread_ids: bool,
+    pub thread_names: bool,
+    /// Log an event as each span closes, with the time it spent busy and idle.
+    pub span_close_events: bool,
+    pub color: ColorMode,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        Self {
+            style: LogStyle::Full,
+            timestamp: TimestampFormat::Utc,
+            target: true,
+            file_line: false,
+            thread_ids: false,
+            thread_names: false,
+            span_close_events: false,
+            color: ColorMode::Auto,
+        }
+    }
+}
+
+impl LogFormat {
+    /// Describes the format, so a sink can tell when it must be rebuilt.
+    pub fn signature(&self) -> String {
+        format!("{self:?}")
+    }
+
+    /// Build a text layer in this format. `is_terminal` says whether `writer` is a terminal,
+    /// for resolving the colour mode.
+    pub fn layer<W>(&self, writer: W, is_terminal: bool) -> BaseLayer
+    where
+        W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+    {
+        let ansi = self.color.use_ansi(is_terminal);
+        let span_events = if self.span_close_events {
+            FmtSpan::CLOSE
+        } else {
+            FmtSpan::NONE
+        };
+
+        let layer = fmt::Layer::new()
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .with_target(self.target)
+            .with_file(self.file_line)
+            .with_line_number(self.file_line)
+            .with_thread_ids(self.thread_ids)
+            .with_thread_names(self.thread_names)
+            .with_span_events(span_events);
+
+        match (self.style, ansi) {
+            (LogStyle::Full, true) => self.with_timer(layer),
+            (LogStyle::Full, false) => {
+                self.with_timer(layer.fmt_fields(PlainFields::<DefaultFields>::default()))
+            }
+            (LogStyle::Compact, true) => self.with_timer(layer.compact()),
+            (LogStyle::Compact, false) => self.with_timer(
+                layer
+                    .compact()
+                    .fmt_fields(PlainFields::<DefaultFields>::default()),
+            ),
+            (LogStyle::Pretty, true) => self.with_timer(layer.pretty()),
+            (LogStyle::Pretty, false) => {
+                self.with_timer(layer.pretty().fmt_fields(PlainFields::<Pretty>::default()))
+            }
+            (LogStyle::Json, _) => Box::new(JsonSpanBackfill(self.with_timer(layer.json()))),
+        }
+    }
+
+    fn with_timer<N, E, W>(
+        &self, layer: fmt::Layer<Registry, N, Format<E, SystemTime>, W>,
+    ) -> BaseLayer
+    where
+        N: for<'writer> FormatFields<'writer> + Send + Sync + 'static,
+        E: Send + Sync + 'static,
+        Format<E, SystemTime>: FormatEvent<Registry, N>,
+        Format<E, ChronoLocal>: FormatEvent<Registry, N>,
+        Format<E, Uptime>: FormatEvent<Registry, N>,
+        Format<E, ()>: FormatEvent<Registry, N>,
+        W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+    {
+        match self.timestamp {
+            TimestampFormat::Utc => layer.boxed(),
+            TimestampFormat::Local => layer.with_timer(ChronoLocal::rfc_3339()).boxed(),
+            TimestampFormat::Uptime => layer.with_timer(Uptime::default()).boxed(),
+            // `Layer::without_time` would also drop span close timings, so only the event
+            // format loses its timestamp.
+            TimestampFormat::None => layer
+                .map_event_format(|format| format.without_time())
+                .boxed(),
+        }
+    }
+}
+
+/// The JSON formatter panics on spans without JSON fields, which spans opened before the
+/// sink was attached never got. This gives them empty fields first.
+struct JsonSpanBackfill(BaseLayer);
+
+impl JsonSpanBackfill {
+    fn backfill<'a>(spans: impl Iterator<Item = SpanRef<'a, Registry>>) {
+        for span in spans {
+            let mut extensions = span.extensions_mut();
+            if extensions
+                .get_mut::<FormattedFields<JsonFields>>()
+                .is_none()
+            {
+                extensions.insert(FormattedFields::<JsonFields>::new("{}".to_string()));
+            }
+        }
+    }
+}
+
+impl Layer<Registry> for JsonSpanBackfill {
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, Registry>) {
+        self.0.on_new_span(attrs, id, ctx);
+    }
+
+    fn on_record(&self, span: &span::Id, values: &span::Record<'_>, ctx: Context<'_, Registry>) {
+        self.0.on_record(span, values, ctx);
+    }
+
+    fn on_follows_from(&self, span: &span::Id, follows: &span::Id, ctx: Context<'_, Registry>) {
+        self.0.on_follows_from(span, follows, ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, Registry>) {
+        if let Some(scope) = ctx.event_scope(event) {
+            Self::backfill(scope);
+        }
+        self.0.on_event(event, ctx);
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, Registry>) {
+        self.0.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, Registry>) {
+        self.0.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, Registry>) {
+        if let Some(span) = ctx.span(&id) {
+            Self::backfill(span.scope());
+        }
+        self.0.on_close(id, ctx);
+    }
+
+    fn on_id_change(&self, old: &span::Id, new: &span::Id, ctx: Context<'_, Registry>) {
+        self.0.on_id_change(old, new, ctx);
+    }
+}
+
+#[cfg(test)]
+mod log_format_tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::Level;
+
+    use super::{ColorMode, LogFormat, LogStyle, TimestampFormat};
+    use crate::logging::LoggingManager;
+
+    #[test]
+    fn options_are_parsed_case_insensitively() {
+        assert_eq!(Some(TimestampFormat::Uptime), "Uptime".parse().ok());
+        assert_eq!(Some(ColorMode::Never), "NEVER".parse().ok());
+        assert_eq!(Some(LogStyle::Json), "Json".parse().ok());
+        assert!("tomorrow".parse::<TimestampFormat>().is_err());
+    }
+
+    #[test]
+    fn layer_follows_format_options() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = output.clone();
+        let format = LogFormat {
+            timestamp: TimestampFormat::None,
+            target: false,
+            file_line: true,
+            span_close_events: true,
+            ..LogFormat::default()
+        };
+
+        let mut log_manager = LoggingManager::new();
+        let _guard = log_manager.build_scoped().unwrap();
+        log_manager.attach_sink(
+            "formatted",
+            Level::INFO,
+            format.layer(move || WriteHandle(writer.clone()), false),
+        );
+
+        tracing::info_span!("work").in_scope(|| tracing::info!("Formatted event"));
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(2, lines.len());
+        assert!(lines[0].trim_start().starts_with("INFO work: "));
+        assert!(lines[0].contains("format.rs:"));
+        assert!(!lines[0].contains("utils::logging::format"));
+        assert!(lines[1].contains("close time.busy="));
+    }
+
+    #[test]
+    fn json_sink_attached_inside_a_span() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = output.clone();
+        let format = LogFormat {
+            style: LogStyle::Json,
+            span_close_events: true,
+            ..LogFormat::default()
+        };
+
+        let mut log_manager = LoggingManager::new();
+        let _guard = log_manager.build_scoped().unwrap();
+        // Another sink is needed for the span to be recorded before the JSON sink exists.
+        log_manager.attach_sink(
+            "text",
+            Level::INFO,
+            LogFormat::default().layer(std::io::sink, false),
+        );
+
+        tracing::info_span!("outer", id = 7).in_scope(|| {
+            log_manager.attach_sink(
+                "json",
+                Level::INFO,
+                format.layer(move || WriteHandle(writer.clone()), false),
+            );
+            tracing::info!(answer = 42, "Structured event");
+        });
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(2, lines.len());
+        assert!(lines[0].starts_with('{'));
+        assert!(lines[0].contains("\"answer\":42"));
+        assert!(lines[0].contains("\"name\":\"outer\""));
+        assert!(lines[1].contains("\"message\":\"close\""));
+    }
+
+    struct WriteHandle(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for WriteHandle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
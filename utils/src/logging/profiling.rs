@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::ThreadId,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tracing::{span, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan};
+
+use crate::core_types::{CoreError, CoreResult};
+
+/// The file format written by the profiler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// Folded stacks with microsecond counts, as read by `inferno` and `flamegraph.pl`.
+    Folded,
+    /// Chrome `trace_event` JSON, as read by Perfetto and `chrome://tracing`.
+    Chrome,
+}
+
+impl ProfileFormat {
+    /// Pick a format from the file extension, defaulting to folded stacks.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::Chrome,
+            _ => Self::Folded,
+        }
+    }
+}
+
+/// A span on a thread's stack, waiting to be exited.
+struct ActiveFrame {
+    id: span::Id,
+    stack: String,
+    started: Instant,
+    children: Duration,
+}
+
+/// A completed enter/exit interval of a span.
+struct CompletedSpan {
+    name: &'static str,
+    target: &'static str,
+    thread: u64,
+    started: Duration,
+    duration: Duration,
+}
+
+#[derive(Default)]
+struct ProfileData {
+    threads: HashMap<ThreadId, (u64, Vec<ActiveFrame>)>,
+    /// Exclusive time spent in each folded stack.
+    folded: HashMap<String, Duration>,
+    completed: Vec<CompletedSpan>,
+}
+
+/// Collects span timings and writes them out as a profile.
+#[derive(Clone)]
+pub struct Profiler {
+    epoch: Instant,
+    path: PathBuf,
+    format: ProfileFormat,
+    data: Arc<Mutex<ProfileData>>,
+}
+
+impl std::fmt::Debug for Profiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Profiler")
+            .field("path", &self.path)
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Profiler {
+    pub fn new(path: PathBuf, format: ProfileFormat) -> Self {
+        Self {
+            epoch: Instant::now(),
+            path,
+            format,
+            data: Arc::new(Mutex::new(ProfileData::default())),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn layer(&self) -> ProfilingLayer {
+        ProfilingLayer {
+            profiler: self.clone(),
+        }
+    }
+
+    /// Write the profile collected so far to the configured file.
+    pub fn write(&self) -> CoreResult<()> {
+        let data = self.data.lock();
+        let mut output = BufWriter::new(File::create(&self.path).map_err(CoreError::from)?);
+
+        match self.format {
+            ProfileFormat::Folded => write_folded(&mut output, &data),
+            ProfileFormat::Chrome => write_chrome(&mut output, &data),
+        }
+        .and_then(|_| output.flush())
+        .map_err(CoreError::from)?;
+
+        Ok(())
+    }
+}
+
+fn write_folded(output: &mut impl Write, data: &ProfileData) -> std::io::Result<()> {
+    let mut stacks: Vec<(&String, &Duration)> = data.folded.iter().collect();
+    stacks.sort();
+
+    for (stack, time) in stacks {
+        let micros = time.as_micros();
+        if micros > 0 {
+            writeln!(output, "{stack} {micros}")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn escape_json(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_chrome(output: &mut impl Write, data: &ProfileData) -> std::io::Result<()> {
+    let pid = std::process::id();
+
+    writeln!(output, "{{\"traceEvents\":[")?;
+    for (index, span) in data.completed.iter().enumerate() {
+        let separator = if index + 1 < data.completed.len() {
+            ","
+        } else {
+            ""
+        };
+        writeln!(
+            output,
+            "{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":{pid},\"tid\":{}}}{separator}",
+            escape_json(span.name),
+            escape_json(span.target),
+            span.started.as_micros(),
+            span.duration.as_micros(),
+            span.thread,
+        )?;
+    }
+    writeln!(output, "],\"displayTimeUnit\":\"ms\"}}")
+}
+
+/// A layer timing every span enter and exit on behalf of a `Profiler`.
+pub struct ProfilingLayer {
+    profiler: Profiler,
+}
+
+impl<S> tracing_subscriber::Layer<S> for ProfilingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let stack = span
+            .scope()
+            .from_root()
+            .map(|span| span.name())
+            .collect::<Vec<&str>>()
+            .join(";");
+
+        let mut data = self.profiler.data.lock();
+        let next_thread = data.threads.len() as u64 + 1;
+        let (_, frames) = data
+            .threads
+            .entry(std::thread::current().id())
+            .or_insert_with(|| (next_thread, Vec::new()));
+
+        frames.push(ActiveFrame {
+            id: id.clone(),
+            stack,
+            started: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let exited = Instant::now();
+        let metadata = ctx.metadata(id);
+
+        let mut data = self.profiler.data.lock();
+        let Some((thread, frames)) = data.threads.get_mut(&std::thread::current().id()) else {
+            return;
+        };
+        let thread = *thread;
+
+        let Some(position) = frames.iter().rposition(|frame| &frame.id == id) else {
+            return;
+        };
+        let frame = frames.remove(position);
+        let duration = exited.duration_since(frame.started);
+
+        if let Some(parent) = frames.last_mut() {
+            parent.children += duration;
+        }
+
+        *data.folded.entry(frame.stack).or_default() += duration.saturating_sub(frame.children);
+
+        if let Some(metadata) = metadata {
+            data.completed.push(CompletedSpan {
+                name: metadata.name(),
+                target: metadata.target(),
+                thread,
+                started: frame.started.duration_since(self.profiler.epoch),
+                duration,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod profiling_tests {
+    use std::path::PathBuf;
+
+    use tracing::Level;
+    use tracing_subscriber::Layer;
+
+    use super::{ProfileFormat, Profiler};
+    use crate::logging::LoggingManager;
+
+    #[test]
+    fn format_is_inferred_from_extension() {
+        assert_eq!(
+            ProfileFormat::Chrome,
+            ProfileFormat::from_path(&PathBuf::from("trace.json"))
+        );
+        assert_eq!(
+            ProfileFormat::Folded,
+            ProfileFormat::from_path(&PathBuf::from("trace.folded"))
+        );
+    }
+
+    #[test]
+    fn nested_spans_are_folded_into_stacks() {
+        let path = std::env::temp_dir().join(format!("profile-test-{}.folded", std::process::id()));
+        let profiler = Profiler::new(path.clone(), ProfileFormat::Folded);
+        let mut log_manager = LoggingManager::new();
+        let _guard = log_manager.build_scoped().unwrap();
+        log_manager.attach_sink("profile", Level::TRACE, profiler.layer().boxed());
+
+        tracing::info_span!("outer").in_scope(|| {
+            tracing::info_span!("inner").in_scope(|| {
+                std::thread::sleep(std::time::Duration::from_millis(2));
+            });
+        });
+
+        profiler.write().unwrap();
+        let folded = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(folded.lines().any(|line| line.starts_with("outer;inner ")));
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    cell::Cell,
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Mutex, RwLock};
+use tracing::{
+    dispatcher::{self, Dispatch},
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::layer::Context;
+
+use crate::{
+    core_types::{CoreError, CoreResult},
+    random::Xorshift,
+};
+
+thread_local! {
+    /// Set while a suppression summary is being emitted so the summary itself isn't limited.
+    static EMITTING_SUMMARY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// What makes two events "identical" for rate limiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// Events from the same `tracing` macro invocation.
+    Callsite,
+    /// Events from the same callsite with the same formatted message.
+    Message,
+}
+
+impl FromStr for RateLimitKey {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "callsite" => Ok(Self::Callsite),
+            "message" => Ok(Self::Message),
+            other => Err(CoreError::LoggingConfigError(format!(
+                "Unknown rate limit key \"{other}\", expected \"callsite\" or \"message\"."
+            ))),
+        }
+    }
+}
+
+/// Settings for both rate limiting and sampling.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Turns sampling off along with rate limiting when false.
+    pub enabled: bool,
+    pub max_events: u64,
+    pub interval: Duration,
+    pub key: RateLimitKey,
+    /// Probability of keeping an event, indexed by level from TRACE to ERROR.
+    pub sample_rates: [f64; 5],
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_events: 100,
+            interval: Duration::from_secs(1),
+            key: RateLimitKey::Callsite,
+            sample_rates: [1.0; 5],
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Build a config from raw settings, validating the key and sample rates.
+    /// Sample rates are ordered from TRACE to ERROR.
+    pub fn new(
+        enabled: bool, max_events: u64, interval_ms: u64, key: &str, sample_rates: [f64; 5],
+    ) -> CoreResult<Self> {
+        if let Some(rate) = sample_rates
+            .iter()
+            .find(|rate| !(0.0..=1.0).contains(*rate))
+        {
+            return Err(CoreError::LoggingConfigError(format!(
+                "Sample rate {rate} must be between 0.0 and 1.0."
+            ))
+            .into());
+        }
+
+        Ok(Self {
+            enabled,
+            max_events,
+            interval: Duration::from_millis(interval_ms),
+            key: key.parse()?,
+            sample_rates,
+        })
+    }
+
+    pub fn with_sample_rate(mut self, level: Level, rate: f64) -> Self {
+        self.sample_rates[level_index(&level)] = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    fn sample_rate(&self, level: &Level) -> f64 {
+        self.sample_rates[level_index(level)]
+    }
+}
+
+fn level_index(level: &Level) -> usize {
+    match *level {
+        Level::TRACE => 0,
+        Level::DEBUG => 1,
+        Level::INFO => 2,
+        Level::WARN => 3,
+        Level::ERROR => 4,
+    }
+}
+
+#[derive(Debug)]
+struct RateLimitWindow {
+    started: Instant,
+    count: u64,
+    suppressed: u64,
+    level: Level,
+    description: String,
+}
+
+/// A closed window that suppressed events and is due a summary.
+#[derive(Debug)]
+struct SuppressionSummary {
+    level: Level,
+    suppressed: u64,
+    description: String,
+}
+
+/// The window an event counts towards, with the message it was keyed by for message keys.
+fn key_for(event: &Event<'_>, key: RateLimitKey) -> (u64, Option<String>) {
+    let mut hasher = DefaultHasher::new();
+    event.metadata().callsite().hash(&mut hasher);
+
+    match key {
+        RateLimitKey::Callsite => (hasher.finish(), None),
+        RateLimitKey::Message => {
+            let mut visitor = MessageVisitor::default();
+            event.record(&mut visitor);
+            visitor.message.hash(&mut hasher);
+
+            (hasher.finish(), Some(visitor.message))
+        }
+    }
+}
+
+/// Where an event comes from, and its message if it was keyed by one, for summaries.
+fn describe(event: &Event<'_>, message: Option<&str>) -> String {
+    let metadata = event.metadata();
+    let location = format!(
+        "{}:{}",
+        metadata.file().unwrap_or(metadata.target()),
+        metadata.line().unwrap_or(0)
+    );
+
+    match message {
+        Some(message) => format!("{location} \"{message}\""),
+        None => location,
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        }
+    }
+}
+
+/// Shared state deciding which events are dropped by sampling or rate limiting.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RwLock<RateLimitConfig>,
+    windows: Mutex<HashMap<u64, RateLimitWindow>>,
+    /// Summaries of windows closed by a new event, waiting for the next tick.
+    pending: Mutex<Vec<SuppressionSummary>>,
+    random: Xorshift,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimitConfig::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            windows: Mutex::new(HashMap::new()),
+            pending: Mutex::new(Vec::new()),
+            random: Xorshift::new(),
+        }
+    }
+
+    pub fn config(&self) -> RateLimitConfig {
+        self.config.read().clone()
+    }
+
+    pub fn set_config(&self, config: RateLimitConfig) {
+        *self.config.write() = config;
+    }
+
+    /// Decide whether an event gets through. Summaries of windows this closes are left for
+    /// the ticker, as logging from inside the dispatch of another event would drop them.
+    fn allow(&self, event: &Event<'_>) -> bool {
+        let config = self.config.read();
+        let level = event.metadata().level();
+
+        if !config.enabled {
+            return true;
+        }
+
+        let sample_rate = config.sample_rate(level);
+        if sample_rate < 1.0 && self.random.next_f64() >= sample_rate {
+            return false;
+        }
+
+        let now = Instant::now();
+        let (key, message) = key_for(event, config.key);
+
+        let mut windows = self.windows.lock();
+        // Only described once per window, as most events land in a window already open.
+        let window = windows.entry(key).or_insert_with(|| RateLimitWindow {
+            started: now,
+            count: 0,
+            suppressed: 0,
+            level: *level,
+            description: describe(event, message.as_deref()),
+        });
+
+        if now.duration_since(window.started) >= config.interval {
+            if window.suppressed > 0 {
+                self.pending.lock().push(SuppressionSummary {
+                    level: window.level,
+                    suppressed: window.suppressed,
+                    description: window.description.clone(),
+                });
+            }
+            window.started = now;
+            window.count = 0;
+            window.suppressed = 0;
+        }
+
+        window.count += 1;
+        if window.count > config.max_events {
+            window.suppressed += 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Close every expired window, returning summaries for those that suppressed events
+    /// along with any left pending.
+    fn sweep(&self, now: Instant, interval: Duration) -> Vec<SuppressionSummary> {
+        let mut windows = self.windows.lock();
+        let mut summaries = std::mem::take(&mut *self.pending.lock());
+
+        windows.retain(|_, window| {
+            if now.duration_since(window.started) < interval {
+                return true;
+            }
+
+            if window.suppressed > 0 {
+                summaries.push(SuppressionSummary {
+                    level: window.level,
+                    suppressed: window.suppressed,
+                    description: window.description.clone(),
+                });
+            }
+
+            false
+        });
+
+        summaries
+    }
+
+    /// Emit summaries for every window still holding suppressed events.
+    /// Call before exiting so no suppression goes unreported.
+    pub fn flush(&self) {
+        let mut summaries = std::mem::take(&mut *self.pending.lock());
+        summaries.extend(
+            self.windows
+                .lock()
+                .drain()
+                .filter(|(_, window)| window.suppressed > 0)
+                .map(|(_, window)| SuppressionSummary {
+                    level: window.level,
+                    suppressed: window.suppressed,
+                    description: window.description,
+                }),
+        );
+
+        emit_summaries(summaries);
+    }
+
+    /// Summarise windows as they close, from a background thread logging through
+    /// `dispatch`, so a storm that stops is reported without waiting for another event.
+    /// The thread ends once the limiter or the dispatcher is dropped.
+    pub(super) fn start_ticker(self: &Arc<Self>, dispatch: &Dispatch) {
+        let limiter = Arc::downgrade(self);
+        let dispatch = dispatch.downgrade();
+
+        let spawned = thread::Builder::new()
+            .name("rate-limit-ticker".to_string())
+            .spawn(move || loop {
+                let interval = match limiter.upgrade() {
+                    Some(limiter) => limiter.config.read().interval,
+                    None => return,
+                };
+                thread::sleep(interval.max(MIN_TICK));
+
+                let (Some(limiter), Some(dispatch)) = (limiter.upgrade(), dispatch.upgrade())
+                else {
+                    return;
+                };
+                let interval = limiter.config.read().interval;
+                let summaries = limiter.sweep(Instant::now(), interval);
+                dispatcher::with_default(&dispatch, || emit_summaries(summaries));
+            });
+
+        // Without the ticker, summaries still go out on `flush`.
+        if let Err(e) = spawned {
+            tracing::warn!("Couldn't start the rate limit ticker: {e}");
+        }
+    }
+}
+
+/// The shortest time between sweeps, however short the interval.
+const MIN_TICK: Duration = Duration::from_millis(10);
+
+fn emit_summaries(summaries: Vec<SuppressionSummary>) {
+    if summaries.is_empty() {
+        return;
+    }
+
+    EMITTING_SUMMARY.with(|emitting| emitting.set(true));
+
+    for SuppressionSummary {
+        level,
+        suppressed,
+        description,
+    } in summaries
+    {
+        match level {
+            Level::TRACE => {
+                tracing::trace!(suppressed, source = %description, "Suppressed {suppressed} similar events.")
+            }
+            Level::DEBUG => {
+                tracing::debug!(suppressed, source = %description, "Suppressed {suppressed} similar events.")
+            }
+            Level::INFO => {
+                tracing::info!(suppressed, source = %description, "Suppressed {suppressed} similar events.")
+            }
+            Level::WARN => {
+                tracing::warn!(suppressed, source = %description, "Suppressed {suppressed} similar events.")
+            }
+            Level::ERROR => {
+                tracing::error!(suppressed, source = %description, "Suppressed {suppressed} similar events.")
+            }
+        }
+    }
+
+    EMITTING_SUMMARY.with(|emitting| emitting.set(false));
+}
+
+/// A layer dropping events for every sink once sampled out or over their rate limit.
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for RateLimitLayer
+where
+    S: Subscriber,
+{
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        if EMITTING_SUMMARY.with(Cell::get) {
+            return true;
+        }
+
+        self.limiter.allow(event)
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use std::time::Duration;
+
+    use tracing::Level;
+
+    use super::{RateLimitConfig, RateLimitKey};
+    use crate::{
+        assert_logged,
+        logging::{testing::LogCapture, LoggingManager},
+    };
+
+    #[test]
+    fn events_over_the_limit_are_suppressed_and_summarised() {
+        let capture = LogCapture::new();
+        let mut log_manager = LoggingManager::new()
+            .with_capture_logging(Level::TRACE, capture.clone())
+            .with_rate_limit(RateLimitConfig {
+                enabled: true,
+                max_events: 3,
+                interval: Duration::from_secs(60),
+                key: RateLimitKey::Callsite,
+                ..Default::default()
+            });
+        let _guard = log_manager.build_scoped().unwrap();
+
+        for i in 0..10 {
+            tracing::info!("Looping {i}");
+        }
+        log_manager.flush_rate_limits();
+
+        assert_logged!(capture, message contains "Looping", times = 3);
+        assert_logged!(capture, level = INFO, message = "Suppressed 7 similar events.", field suppressed = 7);
+    }
+
+    #[test]
+    fn windows_are_summarised_once_closed_without_another_event() {
+        let capture = LogCapture::new();
+        let mut log_manager = LoggingManager::new()
+            .with_capture_logging(Level::TRACE, capture.clone())
+            .with_rate_limit(RateLimitConfig {
+                enabled: true,
+                max_events: 1,
+                interval: Duration::from_millis(20),
+                key: RateLimitKey::Callsite,
+                ..Default::default()
+            });
+        let _guard = log_manager.build_scoped().unwrap();
+
+        for i in 0..5 {
+            tracing::warn!("Storming {i}");
+        }
+        std::thread::sleep(Duration::from_millis(200));
+
+        assert_logged!(
+            capture,
+            level = WARN,
+            message = "Suppressed 4 similar events.",
+            times = 1
+        );
+    }
+
+    #[test]
+    fn zero_sample_rate_drops_level() {
+        let capture = LogCapture::new();
+        let mut log_manager = LoggingManager::new()
+            .with_capture_logging(Level::TRACE, capture.clone())
+            .with_rate_limit(
+                RateLimitConfig {
+                    enabled: true,
+                    ..RateLimitConfig::default()
+                }
+                .with_sample_rate(Level::DEBUG, 0.0),
+            );
+        let _guard = log_manager.build_scoped().unwrap();
+
+        tracing::debug!("Dropped");
+        tracing::info!("Kept");
+
+        assert_logged!(capture, message = "Dropped", times = 0);
+        assert_logged!(capture, message = "Kept", times = 1);
+    }
+}
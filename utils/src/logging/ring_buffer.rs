@@ -0,0 +1,197 @@
+use std::{collections::VecDeque, io::Write, path::PathBuf, sync::Arc};
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use tracing_subscriber::fmt::MakeWriter;
+
+pub const DEFAULT_RING_BUFFER_CAPACITY: usize = 1000;
+
+lazy_static! {
+    /// The global crash ring buffer. It lives outside the `LoggingManager` so the panic hook,
+    /// which is installed before logging is initialised, can always reach it.
+    static ref RING_BUFFER: Arc<RingBuffer> = Arc::new(RingBuffer::new(DEFAULT_RING_BUFFER_CAPACITY));
+}
+
+#[derive(Debug)]
+struct RingBufferState {
+    capacity: usize,
+    events: VecDeque<String>,
+    dump_path: Option<PathBuf>,
+}
+
+/// A bounded in-memory store holding the most recent formatted log events.
+/// Once full, the oldest event is discarded for every new one.
+#[derive(Debug)]
+pub struct RingBuffer {
+    state: Mutex<RingBufferState>,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(RingBufferState {
+                capacity,
+                events: VecDeque::with_capacity(capacity),
+                dump_path: None,
+            }),
+        }
+    }
+
+    /// Get the global ring buffer shared by the log layer and the panic hook.
+    pub fn global() -> Arc<RingBuffer> {
+        RING_BUFFER.clone()
+    }
+
+    pub fn push(&self, event: String) {
+        let mut state = self.state.lock();
+
+        if state.capacity == 0 {
+            return;
+        }
+
+        while state.events.len() >= state.capacity {
+            state.events.pop_front();
+        }
+
+        state.events.push_back(event);
+    }
+
+    /// Change the number of retained events, discarding the oldest ones if shrinking.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.state.lock();
+
+        while state.events.len() > capacity {
+            state.events.pop_front();
+        }
+
+        state.capacity = capacity;
+    }
+
+    /// Set the file to dump to. With no path set, dumps go to stderr.
+    pub fn set_dump_path(&self, dump_path: Option<PathBuf>) {
+        self.state.lock().dump_path = dump_path;
+    }
+
+    pub fn snapshot(&self) -> Vec<String> {
+        self.state.lock().events.iter().cloned().collect()
+    }
+
+    /// Write the buffered events to the configured dump file, or stderr if none is set.
+    /// Uses `try_lock` so a panic raised while the buffer is locked can't deadlock the hook.
+    pub fn dump(&self) -> std::io::Result<()> {
+        let Some(state) = self.state.try_lock() else {
+            return Ok(());
+        };
+
+        let mut output: Box<dyn Write> = match &state.dump_path {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                Box::new(std::fs::File::create(path)?)
+            }
+            None => Box::new(std::io::stderr()),
+        };
+
+        writeln!(
+            output,
+            "---- Last {} buffered log events ----",
+            state.events.len()
+        )?;
+        for event in state.events.iter() {
+            output.write_all(event.as_bytes())?;
+            if !event.ends_with('\n') {
+                writeln!(output)?;
+            }
+        }
+        writeln!(output, "---- End of buffered log events ----")?;
+
+        output.flush()
+    }
+}
+
+/// Dump the global ring buffer, ignoring any errors.
+/// Intended for crash paths where there is nothing left to report a failure to.
+pub fn dump_ring_buffer() {
+    let _ = RING_BUFFER.dump();
+}
+
+/// A `MakeWriter` that turns every formatted event into one ring buffer entry.
+#[derive(Debug, Clone)]
+pub struct RingBufferMakeWriter {
+    buffer: Arc<RingBuffer>,
+}
+
+impl RingBufferMakeWriter {
+    pub fn new(buffer: Arc<RingBuffer>) -> Self {
+        Self { buffer }
+    }
+}
+
+impl<'a> MakeWriter<'a> for RingBufferMakeWriter {
+    type Writer = RingBufferWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RingBufferWriter {
+            buffer: self.buffer.clone(),
+            line: Vec::new(),
+        }
+    }
+}
+
+/// Collects the bytes of a single event and pushes them to the ring buffer when dropped.
+pub struct RingBufferWriter {
+    buffer: Arc<RingBuffer>,
+    line: Vec<u8>,
+}
+
+impl Write for RingBufferWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RingBufferWriter {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.buffer
+                .push(String::from_utf8_lossy(&self.line).into_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod ring_buffer_tests {
+    use super::RingBuffer;
+
+    #[test]
+    fn push_discards_oldest_when_full() {
+        let buffer = RingBuffer::new(2);
+
+        buffer.push("one".to_string());
+        buffer.push("two".to_string());
+        buffer.push("three".to_string());
+
+        assert_eq!(
+            vec!["two".to_string(), "three".to_string()],
+            buffer.snapshot()
+        );
+    }
+
+    #[test]
+    fn set_capacity_truncates() {
+        let buffer = RingBuffer::new(3);
+
+        buffer.push("one".to_string());
+        buffer.push("two".to_string());
+        buffer.push("three".to_string());
+        buffer.set_capacity(1);
+
+        assert_eq!(vec!["three".to_string()], buffer.snapshot());
+    }
+}
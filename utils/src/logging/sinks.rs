@@ -0,0 +1,408 @@
+use std::{any::Any, sync::Arc};
+
+use parking_lot::RwLock;
+use tracing::{
+    level_filters::LevelFilter,
+    span::{self, Attributes, Record},
+    subscriber::Interest,
+    Event, Level, Metadata,
+};
+use tracing_subscriber::{
+    filter::Targets,
+    layer::{Context, Filter},
+    Registry,
+};
+
+pub type BaseLayer = Box<dyn tracing_subscriber::Layer<Registry> + Send + Sync>;
+
+/// Anything that must outlive a sink's layer, such as a non-blocking writer's flush guard.
+pub type SinkGuard = Box<dyn Any + Send + Sync>;
+
+/// A named destination for log events with its own level filter.
+pub struct LogSink {
+    pub name: String,
+    /// The sink's level as the default, plus any per-target overrides.
+    filter: Targets,
+    /// Describes the settings the layer was built with. A sink is rebuilt when this changes.
+    signature: String,
+    /// Whether the sink was created from the `LoggingManager` configuration, rather than
+    /// attached by hand. Only managed sinks are detached on refresh.
+    managed: bool,
+    layer: BaseLayer,
+    _guard: Option<SinkGuard>,
+}
+
+impl std::fmt::Debug for LogSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogSink")
+            .field("name", &self.name)
+            .field("filter", &self.filter)
+            .field("signature", &self.signature)
+            .field("managed", &self.managed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The live set of sinks, shared between the `LoggingManager` and the installed subscriber.
+#[derive(Debug, Clone, Default)]
+pub struct SinkSet {
+    sinks: Arc<RwLock<Vec<LogSink>>>,
+}
+
+impl SinkSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn layer(&self) -> DynamicSinksLayer {
+        DynamicSinksLayer {
+            sinks: self.sinks.clone(),
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.sinks
+            .read()
+            .iter()
+            .map(|sink| sink.name.clone())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sinks.read().iter().any(|sink| sink.name == name)
+    }
+
+    /// Whether the named sink is missing or was built with different settings.
+    pub fn needs_rebuild(&self, name: &str, signature: &str) -> bool {
+        !self
+            .sinks
+            .read()
+            .iter()
+            .any(|sink| sink.name == name && sink.signature == signature)
+    }
+
+    /// Add a sink, replacing any existing sink of the same name.
+    pub fn attach(&self, name: &str, level: Level, layer: BaseLayer) {
+        self.insert(LogSink {
+            name: name.to_string(),
+            filter: Targets::new().with_default(level),
+            signature: String::new(),
+            managed: false,
+            layer,
+            _guard: None,
+        });
+    }
+
+    pub(super) fn attach_managed(
+        &self, name: &str, filter: Targets, signature: String, layer: BaseLayer,
+        guard: Option<SinkGuard>,
+    ) {
+        self.insert(LogSink {
+            name: name.to_string(),
+            filter,
+            signature,
+            managed: true,
+            layer,
+            _guard: guard,
+        });
+    }
+
+    fn insert(&self, sink: LogSink) {
+        let replaced = {
+            let mut sinks = self.sinks.write();
+            match sinks.iter().position(|existing| existing.name == sink.name) {
+                Some(index) => Some(std::mem::replace(&mut sinks[index], sink)),
+                None => {
+                    sinks.push(sink);
+                    None
+                }
+            }
+        };
+
+        // Dropped outside the lock, as dropping a guard may flush and log.
+        drop(replaced);
+        rebuild_interest();
+    }
+
+    /// Remove the named sink, returning whether it existed.
+    pub fn detach(&self, name: &str) -> bool {
+        let removed = {
+            let mut sinks = self.sinks.write();
+            sinks
+                .iter()
+                .position(|sink| sink.name == name)
+                .map(|index| sinks.remove(index))
+        };
+        rebuild_interest();
+
+        removed.is_some()
+    }
+
+    /// Detach every sink holding a guard, dropping the guards so background writers flush
+    /// what they've buffered.
+    pub(super) fn detach_guarded(&self) {
+        let removed: Vec<LogSink> = {
+            let mut sinks = self.sinks.write();
+            let (guarded, kept) = std::mem::take(&mut *sinks)
+                .into_iter()
+                .partition(|sink| sink._guard.is_some());
+            *sinks = kept;
+            guarded
+        };
+
+        drop(removed);
+        rebuild_interest();
+    }
+
+    /// Detach every managed sink not named in `keep`.
+    pub(super) fn retain_managed(&self, keep: &[&str]) {
+        let removed: Vec<LogSink> = {
+            let mut sinks = self.sinks.write();
+            let (kept, removed) = std::mem::take(&mut *sinks)
+                .into_iter()
+                .partition(|sink| !sink.managed || keep.contains(&sink.name.as_str()));
+            *sinks = kept;
+            removed
+        };
+
+        drop(removed);
+        rebuild_interest();
+    }
+
+    /// Change the level of the named sink, keeping any per-target overrides, returning
+    /// whether it existed.
+    pub fn set_level(&self, name: &str, level: Level) -> bool {
+        let found = self
+            .sinks
+            .write()
+            .iter_mut()
+            .find(|sink| sink.name == name)
+            .map(|sink| sink.filter = sink.filter.clone().with_default(level))
+            .is_some();
+        rebuild_interest();
+
+        found
+    }
+
+    /// Replace the whole filter of the named sink, returning whether it existed.
+    pub fn set_filter(&self, name: &str, filter: Targets) -> bool {
+        let found = self
+            .sinks
+            .write()
+            .iter_mut()
+            .find(|sink| sink.name == name)
+            .map(|sink| sink.filter = filter)
+            .is_some();
+        rebuild_interest();
+
+        found
+    }
+
+    pub fn filter(&self, name: &str) -> Option<Targets> {
+        self.sinks
+            .read()
+            .iter()
+            .find(|sink| sink.name == name)
+            .map(|sink| sink.filter.clone())
+    }
+
+    pub fn level(&self, name: &str) -> Option<LevelFilter> {
+        self.sinks
+            .read()
+            .iter()
+            .find(|sink| sink.name == name)
+            .and_then(|sink| sink.filter.default_level())
+    }
+}
+
+/// Have every callsite ask the sinks again whether they're interested, and recompute the
+/// maximum level. Must be called without holding the lock, as the sinks are asked straight away.
+fn rebuild_interest() {
+    tracing::callsite::rebuild_interest_cache();
+}
+
+/// A layer dispatching to every sink in a `SinkSet`, filtering events by each sink's filter.
+///
+/// Span lifecycle callbacks go to every sink so span context stays complete for all of them,
+/// no matter when they were attached or what level they were set to.
+pub struct DynamicSinksLayer {
+    sinks: Arc<RwLock<Vec<LogSink>>>,
+}
+
+impl DynamicSinksLayer {
+    /// Whether any sink's filter lets `metadata` through.
+    fn wants(&self, metadata: &Metadata<'_>) -> bool {
+        self.sinks.read_recursive().iter().any(|sink| {
+            sink.filter
+                .would_enable(metadata.target(), metadata.level())
+        })
+    }
+}
+
+// Callbacks use `read_recursive` so a sink emitting events of its own can't deadlock
+// against a writer waiting to reconfigure the set.
+impl tracing_subscriber::Layer<Registry> for DynamicSinksLayer {
+    // Cached interest is rebuilt whenever the sinks or their filters change.
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.wants(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.sinks
+            .read_recursive()
+            .iter()
+            .filter_map(|sink| Filter::<Registry>::max_level_hint(&sink.filter))
+            .max()
+            .or(Some(LevelFilter::OFF))
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, Registry>) -> bool {
+        self.wants(metadata)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &span::Id, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read_recursive().iter() {
+            sink.layer.on_new_span(attrs, id, ctx.clone());
+        }
+    }
+
+    fn on_record(&self, span: &span::Id, values: &Record<'_>, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read_recursive().iter() {
+            sink.layer.on_record(span, values, ctx.clone());
+        }
+    }
+
+    fn on_follows_from(&self, span: &span::Id, follows: &span::Id, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read_recursive().iter() {
+            sink.layer.on_follows_from(span, follows, ctx.clone());
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read_recursive().iter() {
+            let metadata = event.metadata();
+            if sink
+                .filter
+                .would_enable(metadata.target(), metadata.level())
+            {
+                sink.layer.on_event(event, ctx.clone());
+            }
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read_recursive().iter() {
+            sink.layer.on_enter(id, ctx.clone());
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read_recursive().iter() {
+            sink.layer.on_exit(id, ctx.clone());
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read_recursive().iter() {
+            sink.layer.on_close(id.clone(), ctx.clone());
+        }
+    }
+
+    fn on_id_change(&self, old: &span::Id, new: &span::Id, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read_recursive().iter() {
+            sink.layer.on_id_change(old, new, ctx.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod sink_set_tests {
+    use tracing::Level;
+    use tracing_subscriber::Layer;
+
+    use crate::{
+        assert_logged,
+        logging::{testing::LogCapture, LoggingManager},
+    };
+
+    #[test]
+    fn sinks_can_be_attached_and_detached_after_build() {
+        let capture = LogCapture::new();
+        let mut log_manager = LoggingManager::new();
+        let _guard = log_manager.build_scoped().unwrap();
+
+        tracing::info!("Before attaching");
+        log_manager.attach_sink("extra", Level::INFO, capture.layer().boxed());
+        tracing::info!("While attached");
+        tracing::debug!("Below the sink level");
+        assert!(log_manager.detach_sink("extra"));
+        tracing::info!("After detaching");
+
+        assert_logged!(capture, message = "While attached", times = 1);
+        assert_logged!(capture, message = "Below the sink level", times = 0);
+        assert_logged!(capture, message = "Before attaching", times = 0);
+        assert_logged!(capture, message = "After detaching", times = 0);
+    }
+
+    #[test]
+    fn callsites_pick_up_level_changes() {
+        let capture = LogCapture::new();
+        let mut log_manager =
+            LoggingManager::new().with_capture_logging(Level::INFO, capture.clone());
+        let _guard = log_manager.build_scoped().unwrap();
+
+        for i in 0..2 {
+            // Cached as uninteresting on the first pass, until the level changes.
+            tracing::debug!("Pass {i}");
+            log_manager.set_sink_level("capture", Level::DEBUG);
+        }
+
+        assert_logged!(capture, message = "Pass 0", times = 0);
+        assert_logged!(capture, message = "Pass 1", times = 1);
+    }
+
+    #[test]
+    fn refresh_detaches_removed_sinks() {
+        let capture = LogCapture::new();
+        let mut log_manager = LoggingManager::new()
+            .with_capture_logging(Level::TRACE, capture.clone())
+            .with_ring_buffer_logging(Level::TRACE);
+        let _guard = log_manager.build_scoped().unwrap();
+
+        assert!(log_manager
+            .sink_names()
+            .contains(&"ring_buffer".to_string()));
+
+        let log_manager = log_manager.without_ring_buffer_logging();
+        log_manager.refresh().unwrap();
+
+        assert_eq!(vec!["capture".to_string()], log_manager.sink_names());
+    }
+
+    #[test]
+    fn reconfigure_applies_settings_to_a_shared_manager() {
+        let capture = LogCapture::new();
+        let mut log_manager =
+            LoggingManager::new().with_capture_logging(Level::INFO, capture.clone());
+        let _guard = log_manager.build_scoped().unwrap();
+        let log_manager = std::rc::Rc::new(log_manager);
+
+        log_manager
+            .reconfigure(
+                LoggingManager::new()
+                    .with_capture_logging(Level::DEBUG, capture.clone())
+                    .with_ring_buffer_logging(Level::TRACE),
+            )
+            .unwrap();
+        tracing::debug!("After reconfiguring");
+
+        assert!(log_manager
+            .sink_names()
+            .contains(&"ring_buffer".to_string()));
+        assert_logged!(capture, message = "After reconfiguring", times = 1);
+    }
+}
@@ -0,0 +1,362 @@
+//! Helpers for asserting on the events emitted through `tracing` in tests.
+//!
+//! Captures are installed with `tracing::subscriber::set_default`, so they only apply to the
+//! current thread and never touch the global subscriber. Use a current-thread runtime
+//! (the `#[tokio::test]` default) so spawned tasks are captured too.
+
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+use parking_lot::Mutex;
+use tracing::{
+    field::{Field, Visit},
+    subscriber::DefaultGuard,
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan};
+
+pub use tracing::Level;
+
+use super::{LogLayerConfig, LoggingManager};
+
+/// A single event recorded by a `CaptureLayer`.
+#[derive(Debug, Clone)]
+pub struct CapturedEvent {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    pub fields: HashMap<String, String>,
+    pub spans: Vec<String>,
+}
+
+#[derive(Default)]
+struct CapturedEventVisitor {
+    message: String,
+    fields: HashMap<String, String>,
+}
+
+impl Visit for CapturedEventVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.fields
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+}
+
+/// A shareable handle to the events recorded by a `CaptureLayer`.
+#[derive(Debug, Clone, Default)]
+pub struct LogCapture {
+    events: Arc<Mutex<Vec<CapturedEvent>>>,
+}
+
+impl LogCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn layer(&self) -> CaptureLayer {
+        CaptureLayer {
+            events: self.events.clone(),
+        }
+    }
+
+    pub fn events(&self) -> Vec<CapturedEvent> {
+        self.events.lock().clone()
+    }
+
+    pub fn matching(&self, matcher: &LogMatcher) -> Vec<CapturedEvent> {
+        self.events
+            .lock()
+            .iter()
+            .filter(|event| matcher.matches(event))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().clear();
+    }
+}
+
+/// A layer recording every event it sees into a `LogCapture`.
+pub struct CaptureLayer {
+    events: Arc<Mutex<Vec<CapturedEvent>>>,
+}
+
+impl<S> tracing_subscriber::Layer<S> for CaptureLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = CapturedEventVisitor::default();
+        event.record(&mut visitor);
+
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| span.name().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        self.events.lock().push(CapturedEvent {
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+            spans,
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct CaptureLogLayerConfig {
+    pub layer_config: LogLayerConfig,
+    pub capture: LogCapture,
+}
+
+impl CaptureLogLayerConfig {
+    pub(super) fn new(level: Level, capture: LogCapture) -> Self {
+        Self {
+            layer_config: LogLayerConfig::new(level),
+            capture,
+        }
+    }
+}
+
+/// Criteria an event must meet to be counted by `assert_logged!`.
+/// Every criterion left unset matches any event.
+#[derive(Debug, Default, Clone)]
+pub struct LogMatcher {
+    pub level: Option<Level>,
+    pub target: Option<String>,
+    pub message: Option<String>,
+    pub message_contains: Option<String>,
+    pub fields: Vec<(String, String)>,
+    pub span: Option<String>,
+    pub times: Option<usize>,
+}
+
+impl LogMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    pub fn target(mut self, target: impl ToString) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn message(mut self, message: impl ToString) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    pub fn message_contains(mut self, fragment: impl ToString) -> Self {
+        self.message_contains = Some(fragment.to_string());
+        self
+    }
+
+    /// Match a field by its recorded value. Values are compared as strings.
+    pub fn field(mut self, name: &str, value: impl ToString) -> Self {
+        self.fields.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Match events emitted from within a span of the given name.
+    pub fn span(mut self, span: impl ToString) -> Self {
+        self.span = Some(span.to_string());
+        self
+    }
+
+    /// Require exactly this many matching events instead of at least one.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    pub fn matches(&self, event: &CapturedEvent) -> bool {
+        self.level.is_none_or(|level| event.level == level)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| &event.target == target)
+            && self
+                .message
+                .as_ref()
+                .is_none_or(|message| &event.message == message)
+            && self
+                .message_contains
+                .as_ref()
+                .is_none_or(|fragment| event.message.contains(fragment.as_str()))
+            && self
+                .span
+                .as_ref()
+                .is_none_or(|span| event.spans.contains(span))
+            && self
+                .fields
+                .iter()
+                .all(|(name, value)| event.fields.get(name) == Some(value))
+    }
+}
+
+/// Panic with a readable report unless the capture satisfies the matcher.
+#[track_caller]
+pub fn assert_logged_with(capture: &LogCapture, matcher: &LogMatcher) {
+    let found = capture.matching(matcher).len();
+
+    let satisfied = match matcher.times {
+        Some(times) => found == times,
+        None => found > 0,
+    };
+
+    if !satisfied {
+        let expected = matcher
+            .times
+            .map_or("at least 1".to_string(), |times| times.to_string());
+        let captured = capture
+            .events()
+            .iter()
+            .map(|event| {
+                format!(
+                    "  {} {}: {} {:?}",
+                    event.level, event.target, event.message, event.fields
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        panic!(
+            "Expected {expected} event(s) matching {matcher:?}, found {found}.\nCaptured events:\n{captured}"
+        );
+    }
+}
+
+/// A running capture. Events are recorded until this is dropped.
+pub struct ScopedLogCapture {
+    pub capture: LogCapture,
+    _log_manager: LoggingManager,
+    _guard: DefaultGuard,
+}
+
+impl std::ops::Deref for ScopedLogCapture {
+    type Target = LogCapture;
+
+    fn deref(&self) -> &Self::Target {
+        &self.capture
+    }
+}
+
+/// Capture every event at or above `level` emitted on the current thread.
+pub fn capture_logs(level: Level) -> ScopedLogCapture {
+    let capture = LogCapture::new();
+    let mut log_manager = LoggingManager::new().with_capture_logging(level, capture.clone());
+    let guard = log_manager
+        .build_scoped()
+        .expect("Failed to build capturing log manager.");
+
+    ScopedLogCapture {
+        capture,
+        _log_manager: log_manager,
+        _guard: guard,
+    }
+}
+
+/// Build a `LogMatcher` from a list of comma-separated criteria.
+///
+/// Supported criteria are `level = LEVEL`, `target = "..."`, `message = "..."`,
+/// `message contains "..."`, `span = "..."`, `field name = value` and `times = N`.
+#[macro_export]
+macro_rules! log_matcher {
+    (@build $m:expr;) => { $m };
+    (@build $m:expr; level = $level:ident $(, $($rest:tt)*)?) => {
+        $crate::log_matcher!(@build $m.level($crate::logging::testing::Level::$level); $($($rest)*)?)
+    };
+    (@build $m:expr; target = $target:expr $(, $($rest:tt)*)?) => {
+        $crate::log_matcher!(@build $m.target($target); $($($rest)*)?)
+    };
+    (@build $m:expr; message contains $fragment:expr $(, $($rest:tt)*)?) => {
+        $crate::log_matcher!(@build $m.message_contains($fragment); $($($rest)*)?)
+    };
+    (@build $m:expr; message = $message:expr $(, $($rest:tt)*)?) => {
+        $crate::log_matcher!(@build $m.message($message); $($($rest)*)?)
+    };
+    (@build $m:expr; span = $span:expr $(, $($rest:tt)*)?) => {
+        $crate::log_matcher!(@build $m.span($span); $($($rest)*)?)
+    };
+    (@build $m:expr; field $name:ident = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::log_matcher!(@build $m.field(stringify!($name), $value); $($($rest)*)?)
+    };
+    (@build $m:expr; times = $times:expr $(, $($rest:tt)*)?) => {
+        $crate::log_matcher!(@build $m.times($times); $($($rest)*)?)
+    };
+    ($($criteria:tt)*) => {
+        $crate::log_matcher!(@build $crate::logging::testing::LogMatcher::new(); $($criteria)*)
+    };
+}
+
+/// Assert that a `LogCapture` recorded events matching the given criteria.
+///
+/// ```ignore
+/// let logs = capture_logs(Level::TRACE);
+/// tracing::warn!(x = 3, "Disk almost full");
+/// assert_logged!(logs, level = WARN, message contains "Disk", field x = 3);
+/// ```
+#[macro_export]
+macro_rules! assert_logged {
+    ($capture:expr, $($criteria:tt)+) => {
+        $crate::logging::testing::assert_logged_with(&$capture, &$crate::log_matcher!($($criteria)+))
+    };
+}
+
+#[cfg(test)]
+mod log_capture_tests {
+    use super::{capture_logs, Level};
+
+    #[test]
+    fn assert_logged_matches_level_message_and_fields() {
+        let logs = capture_logs(Level::TRACE);
+
+        tracing::warn!(x = 3, "Disk almost full");
+        tracing::trace!("Ignored");
+
+        crate::assert_logged!(logs, level = WARN, message contains "Disk", field x = 3);
+        crate::assert_logged!(logs, level = TRACE, times = 1);
+        crate::assert_logged!(logs, level = ERROR, times = 0);
+    }
+
+    #[test]
+    fn capture_respects_level() {
+        let logs = capture_logs(Level::INFO);
+
+        tracing::debug!("Too verbose");
+
+        crate::assert_logged!(logs, message = "Too verbose", times = 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected at least 1 event(s)")]
+    fn assert_logged_panics_without_match() {
+        let logs = capture_logs(Level::TRACE);
+
+        crate::assert_logged!(logs, level = ERROR);
+    }
+}
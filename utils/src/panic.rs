@@ -1,6 +1,9 @@
 use tracing::error;
 
-use crate::core_types::{CoreError, CoreResult};
+use crate::{
+    core_types::{CoreError, CoreResult},
+    logging::ring_buffer::dump_ring_buffer,
+};
 
 pub fn initialize_panic_handler() -> CoreResult<()> {
     let (panic_hook, eyre_hook) = color_eyre::config::HookBuilder::default()
@@ -24,6 +27,9 @@ pub fn initialize_panic_handler() -> CoreResult<()> {
             error!("Error: {}", msg);
         }
 
+        // Dump the events leading up to the panic for post-mortem analysis.
+        dump_ring_buffer();
+
         // If we're not running in debug mode, print an end-user-friendly panic message.
         #[cfg(not(debug_assertions))]
         {
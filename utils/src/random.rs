@@ -0,0 +1,58 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A xorshift generator, for spreading out sampling and retry delays. Neither warrants a
+/// dependency on a full RNG crate, but nothing needing unpredictable numbers should use it.
+#[derive(Debug)]
+pub struct Xorshift(AtomicU64);
+
+impl Default for Xorshift {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Xorshift {
+    /// Seeded from the clock.
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0x2545_f491_4f6c_dd1d);
+
+        Self::with_seed(seed)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        // Xorshift never leaves zero.
+        Self(AtomicU64::new(seed | 1))
+    }
+
+    /// A number from 0 up to, but not including, 1.
+    pub fn next_f64(&self) -> f64 {
+        let mut x = self.0.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0.store(x, Ordering::Relaxed);
+
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod random_tests {
+    use super::Xorshift;
+
+    #[test]
+    fn numbers_are_spread_below_one() {
+        let random = Xorshift::with_seed(42);
+        let numbers: Vec<f64> = (0..1000).map(|_| random.next_f64()).collect();
+
+        assert!(numbers.iter().all(|number| (0.0..1.0).contains(number)));
+        assert!(numbers.iter().any(|number| *number < 0.1));
+        assert!(numbers.iter().any(|number| *number > 0.9));
+    }
+}